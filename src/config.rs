@@ -0,0 +1,363 @@
+//! The config file, `$XDG_CONFIG_HOME/nasty/config.toml`.
+//!
+//! Every value is optional and command line flags take precedence over it:
+//!
+//! ```toml
+//! [notifications]
+//! default_urgency = 1
+//! max = 5
+//!
+//! [workspaces]
+//! wm = "sway"
+//! persistent = ["1-5", "DP-1:6-10"]
+//!
+//! [updates]
+//! lock_file = "/etc/nixos/flake.lock"
+//! transitive = true
+//! interval = 1800
+//! cache_ttl = 600
+//! github_token_file = "/run/secrets/github-token"
+//! sourcehut_token_file = "/run/secrets/sourcehut-token"
+//! channels_url = "https://channels.nixos.org"
+//! ```
+//!
+//! Long running commands reload it when it's written or on `SIGHUP`.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+use std::thread;
+
+use anyhow::{bail, Context};
+use inotify::{Inotify, WatchMask};
+use serde::{Deserialize, Serialize};
+use signal_hook::consts::SIGHUP;
+use signal_hook::iterator::Signals;
+use tokio::sync::watch;
+
+use crate::dnote::client;
+
+use crate::workspaces::persistent::Persistent;
+use crate::workspaces::Compositor;
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Config {
+    pub notifications: Notifications,
+    pub workspaces: Workspaces,
+    pub updates: Updates,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Notifications {
+    /// Urgency of notifications without an urgency hint, 0 (low) to 2 (critical).
+    pub default_urgency: u8,
+    /// Drops the oldest notifications once there are more than this.
+    pub max: Option<usize>,
+}
+
+impl Default for Notifications {
+    fn default() -> Self {
+        Notifications {
+            default_urgency: 1,
+            max: None,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Workspaces {
+    /// Detected from the environment if unset.
+    pub wm: Option<Compositor>,
+    pub persistent: Vec<Persistent>,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Updates {
+    pub lock_file: String,
+    /// Also checks the inputs of inputs.
+    pub transitive: bool,
+    /// Seconds between checks, with `nasty updates --watch` and in the daemon.
+    pub interval: u64,
+    /// Seconds a cached check is used without asking the forge again.
+    pub cache_ttl: u64,
+    /// Used unless `GITHUB_TOKEN` or `GH_TOKEN` is set.
+    pub github_token: Option<String>,
+    /// A file with the token, e.g. a sops or agenix secret, used if no other
+    /// token is set.
+    pub github_token_file: Option<String>,
+    /// A personal access token for sourcehut's API, used unless
+    /// `SOURCEHUT_TOKEN` is set. Without one sourcehut inputs are checked with
+    /// git.
+    pub sourcehut_token: Option<String>,
+    /// A file with the sourcehut token, used if no other token is set.
+    pub sourcehut_token_file: Option<String>,
+    /// Where nixpkgs channels are looked up, e.g. a mirror.
+    pub channels_url: String,
+}
+
+impl Default for Updates {
+    fn default() -> Self {
+        Updates {
+            lock_file: "/etc/nixos/flake.lock".into(),
+            transitive: false,
+            interval: 30 * 60,
+            cache_ttl: 10 * 60,
+            github_token: None,
+            github_token_file: None,
+            sourcehut_token: None,
+            sourcehut_token_file: None,
+            channels_url: "https://channels.nixos.org".into(),
+        }
+    }
+}
+
+/// What tokens are printed as.
+const REDACTED: &str = "<redacted>";
+
+/// Flags of the updates commands, each one that's set takes precedence over
+/// `[updates]`.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct UpdatesFlags {
+    pub lock_file: Option<String>,
+    pub transitive: Option<bool>,
+    /// In seconds.
+    pub interval: Option<u64>,
+}
+
+impl UpdatesFlags {
+    pub fn apply(&self, updates: &Updates) -> Updates {
+        Updates {
+            lock_file: self.lock_file.clone().unwrap_or(updates.lock_file.clone()),
+            transitive: self.transitive.unwrap_or(updates.transitive),
+            interval: self.interval.unwrap_or(updates.interval),
+            ..updates.clone()
+        }
+    }
+}
+
+/// Where the config is read from by default.
+pub fn path() -> PathBuf {
+    let dir = match env::var("XDG_CONFIG_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var("HOME").unwrap_or_default()).join(".config"),
+    };
+    dir.join("nasty").join("config.toml")
+}
+
+impl Config {
+    /// Reads the config at `path`, a missing file is the default config.
+    pub fn load(path: &Path) -> anyhow::Result<Config> {
+        match fs::read_to_string(path) {
+            Ok(contents) => {
+                Config::parse(&contents).with_context(|| format!("Invalid {}", path.display()))
+            }
+            Err(e) if e.kind() == io::ErrorKind::NotFound => Ok(Config::default()),
+            Err(e) => Err(e).with_context(|| format!("Couldn't read {}", path.display())),
+        }
+    }
+
+    pub fn parse(contents: &str) -> anyhow::Result<Config> {
+        let config: Config = toml::from_str(contents)?;
+        if config.notifications.default_urgency > 2 {
+            bail!(
+                "notifications.default_urgency must be 0, 1 or 2, got {}",
+                config.notifications.default_urgency
+            );
+        }
+        if config.updates.interval == 0 {
+            bail!("updates.interval can't be zero");
+        }
+        Ok(config)
+    }
+
+    /// The config as TOML, including every default, with the tokens masked.
+    pub fn to_toml(&self) -> String {
+        let mut config = self.clone();
+        let updates = &mut config.updates;
+        for token in [&mut updates.github_token, &mut updates.sourcehut_token] {
+            if token.is_some() {
+                *token = Some(REDACTED.to_string());
+            }
+        }
+        toml::to_string(&config).expect("The config is always valid TOML")
+    }
+}
+
+/// Loads the config at `path` and keeps it up to date.
+///
+/// A config that fails to load is reported with a notification and the
+/// previous one stays in place.
+pub fn watch(path: PathBuf) -> anyhow::Result<watch::Receiver<Config>> {
+    let (tx, rx) = watch::channel(Config::load(&path)?);
+    let tx = Arc::new(tx);
+    let mut signals = Signals::new([SIGHUP])?;
+    thread::spawn({
+        let (path, tx) = (path.clone(), tx.clone());
+        move || {
+            for _ in signals.forever() {
+                reload(&path, &tx, notify_invalid);
+            }
+        }
+    });
+    if let Err(e) = watch_file(path.clone(), tx, notify_invalid) {
+        eprintln!(
+            "Not watching {} ({e}), send SIGHUP to reload it.",
+            path.display()
+        );
+    }
+    Ok(rx)
+}
+
+/// Reloads the config whenever it's written.
+fn watch_file(
+    path: PathBuf,
+    tx: Arc<watch::Sender<Config>>,
+    on_error: fn(&anyhow::Error),
+) -> io::Result<()> {
+    on_write(&path.clone(), move || reload(&path, &tx, on_error))
+}
+
+/// Calls `on_write` from a new thread whenever the file at `path` is written.
+///
+/// The directory is watched since editors (and nix) tend to replace the file.
+pub fn on_write(path: &Path, mut on_write: impl FnMut() + Send + 'static) -> io::Result<()> {
+    let (Some(dir), Some(name)) = (path.parent(), path.file_name()) else {
+        return Err(io::ErrorKind::InvalidInput.into());
+    };
+    let mut inotify = Inotify::init()?;
+    inotify
+        .watches()
+        .add(dir, WatchMask::CLOSE_WRITE | WatchMask::MOVED_TO)?;
+    let name = name.to_owned();
+    thread::spawn(move || {
+        let mut buffer = [0; 4096];
+        while let Ok(events) = inotify.read_events_blocking(&mut buffer) {
+            if events.into_iter().any(|e| e.name == Some(name.as_os_str())) {
+                on_write();
+            }
+        }
+    });
+    Ok(())
+}
+
+fn reload(path: &Path, tx: &watch::Sender<Config>, on_error: fn(&anyhow::Error)) {
+    match Config::load(path) {
+        Ok(config) => {
+            tx.send_if_modified(|current| {
+                let changed = *current != config;
+                *current = config;
+                changed
+            });
+        }
+        Err(e) => on_error(&e),
+    }
+}
+
+fn notify_invalid(e: &anyhow::Error) {
+    eprintln!("Keeping the previous config: {e:#}");
+    let body = format!("{e:#}");
+    let sent = tokio::runtime::Builder::new_current_thread()
+        .enable_all()
+        .build()
+        .map(|rt| rt.block_on(client::try_notify("Invalid nasty config", &body, 2)));
+    if let Ok(Err(e)) = sent {
+        eprintln!("Couldn't send a notification: {e}");
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn missing_values_are_defaults() {
+        let config = Config::parse("[workspaces]\npersistent = [\"DP-1:1-5\"]").unwrap();
+        assert_eq!(
+            config,
+            Config {
+                workspaces: Workspaces {
+                    wm: None,
+                    persistent: vec!["DP-1:1-5".parse().unwrap()],
+                },
+                ..Default::default()
+            }
+        );
+        assert_eq!(Config::parse(&config.to_toml()).unwrap(), config);
+        let missing = Config::load(Path::new("/nonexistent/nasty.toml")).unwrap();
+        assert_eq!(missing, Config::default());
+    }
+
+    #[test]
+    fn tokens_are_not_printed() {
+        let config = Config::parse("[updates]\ngithub_token = \"ghp_secret\"").unwrap();
+        let printed = config.to_toml();
+        assert!(!printed.contains("ghp_secret"), "{printed}");
+        assert!(
+            printed.contains(r#"github_token = "<redacted>""#),
+            "{printed}"
+        );
+    }
+
+    #[test]
+    fn invalid_configs_are_errors() {
+        for contents in [
+            "[workspaces]\nwm = \"kwin\"",
+            "[workspaces]\npersistent = [\"5\"]",
+            "[notifications]\ndefault_urgency = 3",
+            "[updates]\ninterval = 0",
+            "[updates]\nlockfile = \"flake.lock\"",
+        ] {
+            assert!(Config::parse(contents).is_err(), "{contents}");
+        }
+    }
+
+    #[test]
+    fn flags_take_precedence() {
+        let config = Config::parse("[updates]\ntransitive = true\ninterval = 60").unwrap();
+        let flags = UpdatesFlags {
+            transitive: Some(false),
+            ..Default::default()
+        };
+        assert_eq!(
+            flags.apply(&config.updates),
+            Updates {
+                transitive: false,
+                interval: 60,
+                ..Default::default()
+            }
+        );
+    }
+
+    #[test]
+    fn written_configs_are_reloaded() {
+        let dir = env::temp_dir().join(format!("nasty-config-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("config.toml");
+        fs::write(&path, "").unwrap();
+        let (tx, mut rx) = watch::channel(Config::default());
+        watch_file(path.clone(), Arc::new(tx), |_| ()).unwrap();
+
+        let changed = |rx: &mut watch::Receiver<Config>, secs| {
+            let rt = tokio::runtime::Runtime::new().unwrap();
+            let timeout = std::time::Duration::from_secs(secs);
+            rt.block_on(async { tokio::time::timeout(timeout, rx.changed()).await })
+                .is_ok()
+        };
+        fs::write(&path, "[workspaces]\nwm = \"niri\"").unwrap();
+        assert!(changed(&mut rx, 5));
+        assert_eq!(rx.borrow().workspaces.wm, Some(Compositor::Niri));
+
+        // Invalid configs are ignored.
+        fs::write(&path, "[workspaces]\nwm = \"kwin\"").unwrap();
+        fs::write(dir.join("other.toml"), "").unwrap();
+        assert!(!changed(&mut rx, 1));
+        assert_eq!(rx.borrow().workspaces.wm, Some(Compositor::Niri));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,262 @@
+//! Runs every listener in one process and serves their output to
+//! `nasty subscribe` clients over a Unix socket.
+//!
+//! Clients send a topic name on its own line. The daemon answers `OK` (or
+//! `ERR <reason>`) and then streams the topic's JSON lines, starting with
+//! the latest one. Each line is a full state, so slow clients may skip
+//! intermediate lines.
+
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{self, BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+
+use anyhow::{bail, Context};
+use tokio::io::{AsyncBufReadExt, AsyncWriteExt};
+use tokio::net::UnixListener;
+use tokio::sync::watch;
+
+use crate::config;
+use crate::dnote::server;
+use crate::upgrade;
+use crate::workspaces::{Compositor, ListenOptions, Outputs};
+
+/// The daemon's socket, `$XDG_RUNTIME_DIR/nasty.sock` or in `/tmp` without it.
+pub fn socket_path() -> PathBuf {
+    match env::var("XDG_RUNTIME_DIR") {
+        Ok(dir) if !dir.is_empty() => Path::new(&dir).join("nasty.sock"),
+        _ => env::temp_dir().join("nasty.sock"),
+    }
+}
+
+/// The latest line of every topic.
+pub type Topics = BTreeMap<String, watch::Receiver<Option<String>>>;
+
+/// Publishes each line written to it as the topic's latest state.
+pub struct Publisher {
+    tx: watch::Sender<Option<String>>,
+    buf: Vec<u8>,
+}
+
+impl Publisher {
+    pub fn new(tx: watch::Sender<Option<String>>) -> Publisher {
+        Publisher { tx, buf: vec![] }
+    }
+}
+
+impl Write for Publisher {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            let line = String::from_utf8_lossy(&line[..end]).into_owned();
+            self.tx.send_replace(Some(line));
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Ok(())
+    }
+}
+
+/// Which listeners the daemon runs.
+#[derive(Debug, Clone, Default)]
+pub struct Config {
+    /// Serves `workspaces`, plus `window` and `compositor` where supported.
+    pub compositor: Option<Compositor>,
+    pub workspaces: ListenOptions,
+    /// Serves `notifications`, replacing any other notification daemon.
+    pub notifications: Option<watch::Receiver<config::Config>>,
+    /// Serves `updates` for the config's lock file, with the flags taking
+    /// precedence over `[updates]`.
+    pub updates: Option<(watch::Receiver<config::Config>, config::UpdatesFlags)>,
+}
+
+/// Adds `topic`, returning what publishes its lines.
+fn publish(topics: &mut Topics, topic: &str) -> Publisher {
+    let (tx, rx) = watch::channel(None);
+    topics.insert(topic.into(), rx);
+    Publisher::new(tx)
+}
+
+/// Follows the workspaces, window and compositor topics over one connection
+/// on tokio's blocking pool.
+fn spawn_compositor(wm: Compositor, options: ListenOptions, topics: &mut Topics) {
+    let mut workspaces = publish(topics, "workspaces");
+    let mut window = wm.has_window_listeners().then(|| publish(topics, "window"));
+    let mut compositor = wm
+        .has_window_listeners()
+        .then(|| publish(topics, "compositor"));
+    tokio::task::spawn_blocking(move || {
+        let outputs = Outputs {
+            workspaces: Some(&mut workspaces),
+            window: window.as_mut().map(|out| out as _),
+            compositor: compositor.as_mut().map(|out| out as _),
+        };
+        if let Err(e) = wm.follow(&options, outputs) {
+            eprintln!("The {wm:?} listener stopped: {e:#}");
+        }
+    });
+}
+
+/// Starts the configured listeners, then serves them on `path` until killed.
+pub fn run(config: Config, path: &Path) -> anyhow::Result<()> {
+    let runtime = tokio::runtime::Runtime::new()?;
+    runtime.block_on(async {
+        let listener = bind(path)?;
+        let mut topics = Topics::new();
+        if let Some(wm) = config.compositor {
+            spawn_compositor(wm, config.workspaces.clone(), &mut topics);
+        }
+        if let Some(settings) = config.notifications {
+            let out = Some(Box::new(publish(&mut topics, "notifications")) as _);
+            tokio::spawn(server::serve_to(out, settings));
+        }
+        if let Some((settings, flags)) = config.updates {
+            let mut out = publish(&mut topics, "updates");
+            tokio::spawn(async move {
+                let updates = upgrade::watch_nixos(settings, &flags, &mut out);
+                if let Err(e) = updates.await {
+                    eprintln!("The updates listener stopped: {e:#}");
+                }
+            });
+        }
+        if topics.is_empty() {
+            bail!("Nothing to serve, see `nasty daemon -h`.");
+        }
+        serve(listener, topics).await
+    })
+}
+
+/// Binds the socket, replacing it if it's left over from a previous daemon.
+fn bind(path: &Path) -> anyhow::Result<UnixListener> {
+    if UnixStream::connect(path).is_ok() {
+        bail!("A daemon is already listening on {}.", path.display());
+    }
+    let _ = std::fs::remove_file(path);
+    UnixListener::bind(path).with_context(|| format!("Couldn't bind {}", path.display()))
+}
+
+/// Accepts subscribers until the listener fails.
+pub async fn serve(listener: UnixListener, topics: Topics) -> anyhow::Result<()> {
+    loop {
+        let (stream, _) = listener.accept().await?;
+        let topics = topics.clone();
+        tokio::spawn(async move {
+            // Subscribers going away isn't an error.
+            let _ = serve_subscriber(stream, &topics).await;
+        });
+    }
+}
+
+async fn serve_subscriber(stream: tokio::net::UnixStream, topics: &Topics) -> io::Result<()> {
+    let (read, mut write) = stream.into_split();
+    let mut topic = String::new();
+    tokio::io::BufReader::new(read)
+        .read_line(&mut topic)
+        .await?;
+    let Some(rx) = topics.get(topic.trim()) else {
+        let available: Vec<_> = topics.keys().map(String::as_str).collect();
+        let reply = format!(
+            "ERR Unknown topic {:?}, the daemon serves: {}\n",
+            topic.trim(),
+            available.join(", ")
+        );
+        return write.write_all(reply.as_bytes()).await;
+    };
+    write.write_all(b"OK\n").await?;
+    let mut rx = rx.clone();
+    loop {
+        let line = rx.borrow_and_update().clone();
+        if let Some(line) = line {
+            write.write_all(format!("{line}\n").as_bytes()).await?;
+        }
+        // The listener is gone once its sender is dropped.
+        if rx.changed().await.is_err() {
+            return Ok(());
+        }
+    }
+}
+
+/// Copies a topic's lines from the daemon on `path` to `out`.
+pub fn subscribe(path: &Path, topic: &str, out: &mut impl Write) -> anyhow::Result<()> {
+    let mut stream = UnixStream::connect(path).with_context(|| {
+        format!(
+            "Couldn't connect to {}, is `nasty daemon` running?",
+            path.display()
+        )
+    })?;
+    writeln!(stream, "{topic}")?;
+    let mut lines = BufReader::new(stream).lines();
+    match lines.next().transpose()? {
+        Some(reply) if reply == "OK" => (),
+        Some(reply) => bail!("{}", reply.trim_start_matches("ERR ")),
+        None => bail!("The daemon hung up."),
+    }
+    for line in lines {
+        writeln!(out, "{}", line?)?;
+        out.flush()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn publisher_splits_lines() {
+        let (tx, rx) = watch::channel(None);
+        let mut publisher = Publisher::new(tx);
+        write!(publisher, "{{\"a\":").unwrap();
+        assert_eq!(*rx.borrow(), None);
+        write!(publisher, "1}}\n{{\"a\":2}}\n{{\"a\"").unwrap();
+        assert_eq!(rx.borrow().as_deref(), Some(r#"{"a":2}"#));
+    }
+
+    /// Hands every line to the test thread.
+    struct Lines(std::sync::mpsc::Sender<String>, String);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.push_str(&String::from_utf8_lossy(buf));
+            if self.1.ends_with('\n') {
+                let _ = self.0.send(std::mem::take(&mut self.1));
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn subscribers_get_the_latest_line_then_updates() {
+        let path = env::temp_dir().join(format!("nasty-daemon-{}.sock", std::process::id()));
+        let (tx, rx) = watch::channel(Some("stale".to_string()));
+        tx.send_replace(Some("first".to_string()));
+        let runtime = tokio::runtime::Runtime::new().unwrap();
+        let listener = runtime.block_on(async { bind(&path) }).unwrap();
+        let topics = Topics::from([("workspaces".to_string(), rx)]);
+        runtime.spawn(serve(listener, topics));
+
+        let err = subscribe(&path, "weather", &mut vec![]).unwrap_err();
+        assert!(err.to_string().contains("serves: workspaces"), "{err}");
+
+        let (lines_tx, lines) = std::sync::mpsc::channel();
+        let subscriber = std::thread::spawn({
+            let path = path.clone();
+            move || subscribe(&path, "workspaces", &mut Lines(lines_tx, String::new())).unwrap()
+        });
+        assert_eq!(lines.recv().unwrap(), "first\n");
+        tx.send_replace(Some("second".to_string()));
+        // Ends the topic, closing the subscription.
+        drop(tx);
+        subscriber.join().unwrap();
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(lines.iter().collect::<String>(), "second\n");
+    }
+}
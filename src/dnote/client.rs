@@ -63,6 +63,17 @@ pub async fn notify(
     dbg!(reply);
 }
 
+/// Sends a notification with an urgency from 0 (low) to 2 (critical).
+///
+/// Unlike `notify`, failures are returned, e.g. without a notification server.
+pub async fn try_notify(summary: &str, body: &str, urgency: u8) -> zbus::Result<u32> {
+    let connection = Connection::session().await?;
+    let note = NotificationsProxy::new(&connection).await?;
+    let hints = std::collections::HashMap::from([("urgency", zvariant::Value::U8(urgency))]);
+    note.notify("nasty", 0, "", summary, body, &[], hints, -1)
+        .await
+}
+
 pub async fn close_notification(id: u32) {
     let connection = Connection::session().await.expect("");
     let note = NotificationsProxy::new(&connection).await.expect("");
@@ -1,9 +1,13 @@
 use std::collections::HashMap;
+use std::io::Write;
 
 use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
 use zbus::{interface, Connection};
 use zvariant::{DeserializeDict, SerializeDict, Type};
 
+use crate::config::{self, Config};
+
 #[derive(Serialize, Deserialize)]
 struct Notification {
     app_name: String,
@@ -31,19 +35,28 @@ struct Hints {
     urgency: Option<u8>,
 }
 
+/// Where the notifications are written to, stdout if unset.
+pub type Output = Option<Box<dyn Write + Send + Sync>>;
+
 #[derive(Serialize, Deserialize)]
 struct Notes {
     notifications: HashMap<u32, Notification>,
     priority: Vec<u32>,
     last_id: u32,
+    #[serde(skip)]
+    out: Output,
+    #[serde(skip)]
+    settings: config::Notifications,
 }
 
 impl Notes {
-    fn new() -> Notes {
+    fn new(out: Output, settings: config::Notifications) -> Notes {
         let mut n = Notes {
             notifications: HashMap::new(),
             priority: Vec::new(),
             last_id: 1,
+            out,
+            settings,
         };
         n.on_change();
         n
@@ -53,17 +66,29 @@ impl Notes {
         self.last_id
     }
     fn on_change(&mut self) {
+        self.drop_oldest();
         self.update_urgency();
-        let out = serde_json::to_string(self).expect("");
-        println!("{}", out);
+        let data = serde_json::to_string(self).expect("");
+        match &mut self.out {
+            Some(out) => writeln!(out, "{}", data).expect("Couldn't write notifications"),
+            None => println!("{}", data),
+        }
+    }
+    fn drop_oldest(&mut self) {
+        let Some(max) = self.settings.max else {
+            return;
+        };
+        while self.notifications.len() > max {
+            let oldest = *self.notifications.keys().min().unwrap();
+            self.notifications.remove(&oldest);
+        }
     }
     fn update_urgency(&mut self) {
+        let default_urgency = self.settings.default_urgency;
         self.priority = self.notifications.keys().copied().collect();
         self.priority.sort_by_key(|k| {
-            (
-                10 - self.notifications.get(k).unwrap().hints.urgency.unwrap(),
-                *k,
-            )
+            let urgency = self.notifications.get(k).unwrap().hints.urgency;
+            (10 - urgency.unwrap_or(default_urgency), *k)
         });
     }
 }
@@ -140,12 +165,18 @@ impl Notes {
     }
 }
 
-pub async fn serve() {
+pub async fn serve(config: watch::Receiver<Config>) {
+    serve_to(None, config).await
+}
+
+/// Serves notifications, applying the config's settings whenever it changes.
+pub async fn serve_to(out: Output, mut config: watch::Receiver<Config>) {
+    let settings = config.borrow_and_update().notifications.clone();
     let connection = Connection::session().await.expect("");
     // setup the server
     connection
         .object_server()
-        .at("/org/freedesktop/Notifications", Notes::new())
+        .at("/org/freedesktop/Notifications", Notes::new(out, settings))
         .await
         .expect("");
     // before requesting the name
@@ -154,9 +185,20 @@ pub async fn serve() {
         .await
         .expect("");
 
-    loop {
-        // do something else, wait forever or timeout here:
-        // handling D-Bus messages is done in the background
-        std::future::pending::<()>().await;
+    let notes = connection
+        .object_server()
+        .interface::<_, Notes>("/org/freedesktop/Notifications")
+        .await
+        .expect("");
+    // handling D-Bus messages is done in the background
+    while config.changed().await.is_ok() {
+        let settings = config.borrow_and_update().notifications.clone();
+        let mut notes = notes.get_mut().await;
+        // Other sections changing leaves the notifications as they are.
+        if notes.settings != settings {
+            notes.settings = settings;
+            notes.on_change();
+        }
     }
+    std::future::pending::<()>().await;
 }
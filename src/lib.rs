@@ -1,4 +1,7 @@
+pub mod config;
+pub mod daemon;
 pub mod dnote;
 pub mod notifications;
+pub mod output;
 pub mod upgrade;
 pub mod workspaces;
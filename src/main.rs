@@ -1,12 +1,37 @@
 extern crate nasty;
 
-use clap::{Parser, Subcommand, ValueEnum};
-use nasty::{notifications, upgrade, workspaces};
+use std::io;
+use std::ops::RangeInclusive;
+use std::path::PathBuf;
+use std::time::Duration;
+
+use anyhow::bail;
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use nasty::config::{self, Config};
+use nasty::output::template::Template;
+use nasty::output::{Format, Formatted, Topic};
+use nasty::workspaces::persistent::Persistent;
+use nasty::workspaces::scroll::{self, Direction, Scroll};
+use nasty::workspaces::{Action, Compositor, ListenOptions, Target};
+use nasty::{daemon, notifications, upgrade, workspaces};
+use tokio::sync::watch;
 
 /// A listener cli designed to be used with EWW widgets.
 #[derive(Debug, Parser)]
 #[command(name = "nasty")]
 struct Cli {
+    /// Defaults for every command, flags take precedence
+    #[arg(long, global = true, default_value_os_t = config::path())]
+    config: PathBuf,
+
+    /// How listeners print their state
+    #[arg(short, long, global = true, value_enum, default_value_t = Formats::Json)]
+    format: Formats,
+
+    /// Rendered with the state for `--format template`, e.g. `{active_workspace}`
+    #[arg(short, long, global = true, required_if_eq("format", "template"))]
+    template: Option<Template>,
+
     #[command(subcommand)]
     command: Commands,
 }
@@ -22,29 +47,236 @@ enum Commands {
         close: u32,
     },
 
-    /// Listens to workspace changes
+    /// Listens to workspace changes, or changes them
     #[command()]
     Workspaces {
-        /// The WM being used.
-        #[arg(default_value_t=WindowManagers::Hyprland, value_enum)]
-        wm: WindowManagers,
+        /// The WM being used, detected from the environment by default.
+        #[arg(value_enum)]
+        wm: Option<WindowManagers>,
+
+        /// Workspaces that are always listed, e.g. `1-5` or `DP-1:6-10`
+        #[arg(long, value_delimiter = ',')]
+        persistent: Vec<Persistent>,
+
+        #[command(subcommand)]
+        action: Option<WorkspaceActions>,
+    },
+
+    /// Listens to changes of the focused window
+    #[command()]
+    Window {
+        /// The WM being used, detected from the environment by default.
+        #[arg(value_enum)]
+        wm: Option<WindowManagers>,
+    },
+
+    /// Listens to the submap or binding mode, keyboard layout, special
+    /// workspace, fullscreen and floating state
+    #[command()]
+    Compositor {
+        /// The WM being used, detected from the environment by default.
+        #[arg(value_enum)]
+        wm: Option<WindowManagers>,
+    },
+
+    /// Runs the listeners in the background for `nasty subscribe`
+    #[command()]
+    Daemon {
+        /// The WM being used, detected from the environment by default.
+        #[arg(value_enum)]
+        wm: Option<WindowManagers>,
+
+        /// Workspaces that are always listed, e.g. `1-5` or `DP-1:6-10`
+        #[arg(long, value_delimiter = ',')]
+        persistent: Vec<Persistent>,
+
+        /// Also runs the notification server
+        #[arg(short, long)]
+        notifications: bool,
+
+        /// Also checks the flake lock file for updates, the config's unless
+        /// one is given
+        #[arg(short, long)]
+        updates: Option<Option<String>>,
+
+        /// How often to check for updates, e.g. `90s`, `30m` or `1h`
+        #[arg(long, value_parser = parse_interval)]
+        interval: Option<Duration>,
     },
 
+    /// Prints a topic served by `nasty daemon`, e.g. `workspaces`
+    #[command()]
+    Subscribe { topic: String },
+
     #[command()]
     Updates {
         /// The Package Manager used
         #[arg(default_value_t=PackageManagers::Nix, value_enum)]
         pkg: PackageManagers,
 
-        #[arg(short, long, default_value_t=String::from("/etc/nixos/flake.lock"))]
-        lock_file: String,
+        /// `/etc/nixos/flake.lock` unless it's set in the config
+        #[arg(short, long)]
+        lock_file: Option<String>,
+
+        /// Also checks the inputs of inputs
+        #[arg(long, overrides_with = "no_transitive")]
+        transitive: bool,
+
+        /// Only checks the flake's own inputs, even if the config sets
+        /// `transitive`
+        #[arg(long)]
+        no_transitive: bool,
+
+        /// Keeps checking, printing the updates whenever they change
+        #[arg(short, long)]
+        watch: bool,
+
+        /// How often to check with `--watch`, e.g. `90s`, `30m` or `1h`
+        #[arg(short, long, value_parser = parse_interval)]
+        interval: Option<Duration>,
+    },
+
+    /// Compares the running NixOS system with the lock file
+    #[command()]
+    System {
+        /// `/etc/nixos/flake.lock` unless it's set in the config
+        #[arg(short, long)]
+        lock_file: Option<String>,
     },
+
+    /// Works with the config file
+    #[command(subcommand)]
+    Config(ConfigActions),
+}
+
+#[derive(Debug, Subcommand)]
+enum ConfigActions {
+    /// Validates the config and prints it with every default filled in
+    Check,
+}
+
+/// Workspaces are given by the id the listener prints, or by name.
+#[derive(Debug, Subcommand)]
+enum WorkspaceActions {
+    /// Switches to a workspace, creating it if needed
+    Focus { workspace: Target },
+    /// Moves the focused window to a workspace
+    MoveWindow { workspace: Target },
+    /// Creates a workspace
+    Create { workspace: Target },
+    /// Renames a workspace
+    Rename { workspace: Target, name: String },
+    /// Switches to the next workspace
+    Next(ScrollArgs),
+    /// Switches to the previous workspace
+    Prev(ScrollArgs),
+}
+
+#[derive(Debug, Args)]
+struct ScrollArgs {
+    /// Continue at the other end after the last workspace
+    #[arg(short, long)]
+    wrap: bool,
+    /// Skip workspaces without windows
+    #[arg(short, long)]
+    skip_empty: bool,
+    /// Stay on the focused monitor
+    #[arg(short, long)]
+    per_monitor: bool,
+    /// Workspaces that always exist, e.g. 1-10, no others are visited
+    #[arg(short, long, value_parser = scroll::parse_range)]
+    range: Option<RangeInclusive<i64>>,
+}
+
+impl From<ScrollArgs> for Scroll {
+    fn from(args: ScrollArgs) -> Scroll {
+        Scroll {
+            wrap: args.wrap,
+            skip_empty: args.skip_empty,
+            per_monitor: args.per_monitor,
+            range: args.range,
+        }
+    }
+}
+
+impl WorkspaceActions {
+    /// The action to run on `wm`, `None` if there's nothing to do.
+    fn into_action(self, wm: Compositor) -> anyhow::Result<Option<Action>> {
+        let (scroll, direction) = match self {
+            WorkspaceActions::Focus { workspace } => return Ok(Some(Action::Focus(workspace))),
+            WorkspaceActions::MoveWindow { workspace } => {
+                return Ok(Some(Action::MoveWindow(workspace)))
+            }
+            WorkspaceActions::Create { workspace } => return Ok(Some(Action::Create(workspace))),
+            WorkspaceActions::Rename { workspace, name } => {
+                return Ok(Some(Action::Rename(workspace, name)))
+            }
+            WorkspaceActions::Next(args) => (Scroll::from(args), Direction::Next),
+            WorkspaceActions::Prev(args) => (Scroll::from(args), Direction::Prev),
+        };
+        let target = scroll.target(&wm.current_state()?, direction);
+        Ok(target.map(|id| Action::Focus(Target::Id(id))))
+    }
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
 enum WindowManagers {
+    /// Detect the running WM from the environment.
+    Auto,
     Hyprland,
     Sway,
+    I3,
+    Niri,
+    /// Any compositor with ext-workspace-v1 or river-status, e.g. river.
+    Wayland,
+}
+
+impl WindowManagers {
+    /// The chosen window manager, falling back to the config's and then to
+    /// the one detected from the environment.
+    fn resolve(wm: Option<WindowManagers>, config: &Config) -> anyhow::Result<Compositor> {
+        match wm.or(config.workspaces.wm.map(WindowManagers::from)) {
+            None | Some(WindowManagers::Auto) => workspaces::detect(),
+            Some(WindowManagers::Hyprland) => Ok(Compositor::Hyprland),
+            Some(WindowManagers::Sway) => Ok(Compositor::Sway),
+            Some(WindowManagers::I3) => Ok(Compositor::I3),
+            Some(WindowManagers::Niri) => Ok(Compositor::Niri),
+            Some(WindowManagers::Wayland) => Ok(Compositor::Wayland),
+        }
+    }
+}
+
+impl From<Compositor> for WindowManagers {
+    fn from(wm: Compositor) -> WindowManagers {
+        match wm {
+            Compositor::Hyprland => WindowManagers::Hyprland,
+            Compositor::Sway => WindowManagers::Sway,
+            Compositor::I3 => WindowManagers::I3,
+            Compositor::Niri => WindowManagers::Niri,
+            Compositor::Wayland => WindowManagers::Wayland,
+        }
+    }
+}
+
+#[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
+enum Formats {
+    /// The full state
+    Json,
+    /// Waybar's custom module protocol
+    Waybar,
+    /// The state rendered with `--template`
+    Template,
+}
+
+impl Cli {
+    fn output_format(&self) -> Format {
+        match (self.format, &self.template) {
+            (Formats::Json, _) => Format::Json,
+            (Formats::Waybar, _) => Format::Waybar,
+            (Formats::Template, Some(template)) => Format::Template(template.clone()),
+            (Formats::Template, None) => unreachable!("clap requires a template"),
+        }
+    }
 }
 
 #[derive(ValueEnum, Copy, Clone, Debug, PartialEq, Eq)]
@@ -52,21 +284,193 @@ enum PackageManagers {
     Nix,
 }
 
-fn main() {
+/// The persistent workspaces from the flags, or from the config without any.
+fn listen_options(persistent: Vec<Persistent>, config: watch::Receiver<Config>) -> ListenOptions {
+    match persistent.is_empty() {
+        true => ListenOptions {
+            config: Some(config),
+            ..Default::default()
+        },
+        false => ListenOptions {
+            persistent,
+            config: None,
+        },
+    }
+}
+
+/// Parses a duration such as `90s`, `30m`, `1h` or `1d`, in seconds without a unit.
+fn parse_interval(s: &str) -> Result<Duration, String> {
+    let split = s.find(|c: char| !c.is_ascii_digit()).unwrap_or(s.len());
+    let (n, unit) = s.split_at(split);
+    let n: u64 = n
+        .parse()
+        .map_err(|_| format!("`{s}` doesn't start with a number"))?;
+    let unit_secs = match unit {
+        "" | "s" => 1,
+        "m" => 60,
+        "h" => 60 * 60,
+        "d" => 24 * 60 * 60,
+        _ => return Err(format!("`{unit}` isn't one of s, m, h or d")),
+    };
+    match n.checked_mul(unit_secs) {
+        Some(0) => Err("The interval can't be zero".to_string()),
+        Some(secs) => Ok(Duration::from_secs(secs)),
+        None => Err(format!("`{s}` is too large an interval")),
+    }
+}
+
+fn main() -> anyhow::Result<()> {
     let args = Cli::parse();
+    let config = Config::load(&args.config)?;
+    let format = args.output_format();
+    let stdout = |topic| Formatted::new(format.clone(), topic, io::stdout());
     match args.command {
         //Commands::Notification {} => notifications::send_test_note(),
         Commands::Notifications { server, close } => match (server, close) {
-            (true, _) => notifications::start_server(),
+            (true, _) => {
+                let out = stdout(Topic::Notifications);
+                notifications::start_server(config::watch(args.config)?, Some(Box::new(out)))
+            }
             (false, 0) => println!("Unknown usage, see -h."),
             (false, close) => notifications::close_notification(close),
         },
-        Commands::Workspaces { wm } => match wm {
-            WindowManagers::Hyprland => workspaces::hyprland::listen_and_print(),
-            WindowManagers::Sway => workspaces::sway::listen_and_print(),
-        },
-        Commands::Updates { pkg, lock_file } => match pkg {
-            PackageManagers::Nix => upgrade::nixos(&lock_file),
+        Commands::Workspaces {
+            wm,
+            action: Some(action),
+            ..
+        } => {
+            let wm = WindowManagers::resolve(wm, &config)?;
+            if let Some(action) = action.into_action(wm)? {
+                wm.run(&action)?;
+            }
+        }
+        Commands::Workspaces {
+            wm,
+            persistent,
+            action: None,
+        } => {
+            let options = listen_options(persistent, config::watch(args.config)?);
+            WindowManagers::resolve(wm, &config)?
+                .listen(&options, &mut stdout(Topic::Workspaces))?;
+        }
+        Commands::Window { wm } => {
+            WindowManagers::resolve(wm, &config)?.listen_window(&mut stdout(Topic::Window))?
+        }
+        Commands::Compositor { wm } => WindowManagers::resolve(wm, &config)?
+            .listen_compositor(&mut stdout(Topic::Compositor))?,
+        Commands::Daemon {
+            wm,
+            persistent,
+            notifications,
+            updates,
+            interval,
+        } => {
+            let watched = config::watch(args.config)?;
+            let daemon_config = daemon::Config {
+                compositor: match wm {
+                    Some(_) => Some(WindowManagers::resolve(wm, &config)?),
+                    // The other topics are still useful without a WM.
+                    None => WindowManagers::resolve(None, &config).ok(),
+                },
+                workspaces: listen_options(persistent, watched.clone()),
+                notifications: notifications.then_some(watched.clone()),
+                updates: updates.map(|lock_file| {
+                    let flags = config::UpdatesFlags {
+                        lock_file,
+                        interval: interval.map(|i| i.as_secs()),
+                        ..Default::default()
+                    };
+                    (watched, flags)
+                }),
+            };
+            daemon::run(daemon_config, &daemon::socket_path())?;
+        }
+        Commands::Subscribe { topic } => {
+            let parsed = topic.parse()?;
+            if parsed == Topic::System {
+                bail!("The daemon doesn't serve system, run `nasty system` instead.");
+            }
+            let mut out = stdout(parsed);
+            daemon::subscribe(&daemon::socket_path(), &topic, &mut out)?
+        }
+        Commands::Updates {
+            pkg,
+            lock_file,
+            transitive,
+            no_transitive,
+            watch,
+            interval,
+        } => match pkg {
+            PackageManagers::Nix => {
+                let flags = config::UpdatesFlags {
+                    lock_file,
+                    // Only one of them is set, the last one given.
+                    transitive: (transitive || no_transitive).then_some(transitive),
+                    interval: interval.map(|i| i.as_secs()),
+                };
+                let mut out = stdout(Topic::Updates);
+                let rt = tokio::runtime::Runtime::new()?;
+                match watch {
+                    true => {
+                        let watched = config::watch(args.config)?;
+                        rt.block_on(upgrade::watch_nixos(watched, &flags, &mut out))?
+                    }
+                    false => {
+                        rt.block_on(upgrade::nixos(&flags.apply(&config.updates), &mut out))?
+                    }
+                }
+            }
         },
+        Commands::System { lock_file } => upgrade::system::nixos(
+            &upgrade::system::Paths::default(),
+            &lock_file.unwrap_or(config.updates.lock_file),
+            &mut stdout(Topic::System),
+        )?,
+        Commands::Config(ConfigActions::Check) => print!("{}", config.to_toml()),
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use clap::CommandFactory;
+
+    use super::*;
+
+    #[test]
+    fn intervals_are_parsed() {
+        assert_eq!(parse_interval("90"), Ok(Duration::from_secs(90)));
+        assert_eq!(parse_interval("30m"), Ok(Duration::from_secs(30 * 60)));
+        assert_eq!(parse_interval("2h"), Ok(Duration::from_secs(2 * 60 * 60)));
+        assert!(parse_interval("m").is_err());
+        assert!(parse_interval("5 minutes").is_err());
+        assert!(parse_interval("0s").is_err());
+        assert!(parse_interval(&format!("{}d", u64::MAX / 60)).is_err());
+    }
+
+    #[test]
+    fn the_last_transitive_flag_wins() {
+        let transitive = |args: &[&str]| match Cli::parse_from(args).command {
+            Commands::Updates {
+                transitive,
+                no_transitive,
+                ..
+            } => (transitive, no_transitive),
+            _ => unreachable!(),
+        };
+        assert_eq!(transitive(&["nasty", "updates"]), (false, false));
+        assert_eq!(
+            transitive(&["nasty", "updates", "--no-transitive", "--transitive"]),
+            (true, false)
+        );
+        assert_eq!(
+            transitive(&["nasty", "updates", "--transitive", "--no-transitive"]),
+            (false, true)
+        );
+    }
+
+    #[test]
+    fn cli_is_valid() {
+        Cli::command().debug_assert();
     }
 }
@@ -1,7 +1,9 @@
 use std::collections::HashMap;
 
 use tokio;
+use tokio::sync::watch;
 
+use crate::config::Config;
 use crate::dnote::{client, server};
 
 pub fn send_test_note() {
@@ -25,8 +27,8 @@ pub fn close_notification(id: u32) {
     rt.block_on(future);
 }
 
-pub fn start_server() {
+pub fn start_server(config: watch::Receiver<Config>, out: server::Output) {
     let rt = tokio::runtime::Runtime::new().unwrap();
-    let future = server::serve();
+    let future = server::serve_to(out, config);
     rt.block_on(future);
 }
@@ -0,0 +1,123 @@
+//! Renders the JSON lines of the listeners in other formats.
+//!
+//! Listeners keep writing their state as JSON lines, `Formatted` sits between
+//! them and stdout and rewrites every line.
+
+pub mod template;
+pub mod waybar;
+
+use std::io::{self, Write};
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde_json::Value;
+
+use template::Template;
+use waybar::Waybar;
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub enum Format {
+    /// The listener's state as is.
+    #[default]
+    Json,
+    /// `{"text", "tooltip", "class"}` for waybar's custom modules.
+    Waybar,
+    Template(Template),
+}
+
+/// What a listener reports, which decides the waybar fields.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Topic {
+    Workspaces,
+    Window,
+    Compositor,
+    Notifications,
+    Updates,
+    System,
+}
+
+impl FromStr for Topic {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Topic> {
+        Ok(match s {
+            "workspaces" => Topic::Workspaces,
+            "window" => Topic::Window,
+            "compositor" => Topic::Compositor,
+            "notifications" => Topic::Notifications,
+            "updates" => Topic::Updates,
+            "system" => Topic::System,
+            _ => bail!(
+                "Unknown topic {s:?}, expected workspaces, window, compositor, notifications, updates or system"
+            ),
+        })
+    }
+}
+
+impl Format {
+    pub fn render(&self, topic: Topic, state: &Value) -> String {
+        match self {
+            Format::Json => state.to_string(),
+            Format::Waybar => serde_json::to_string(&Waybar::new(topic, state))
+                .expect("Waybar output is always valid JSON"),
+            Format::Template(template) => template.render(state),
+        }
+    }
+}
+
+/// Writes every JSON line written to it to `out` in `format`.
+pub struct Formatted<W> {
+    format: Format,
+    topic: Topic,
+    out: W,
+    buf: Vec<u8>,
+}
+
+impl<W: Write> Formatted<W> {
+    pub fn new(format: Format, topic: Topic, out: W) -> Formatted<W> {
+        Formatted {
+            format,
+            topic,
+            out,
+            buf: vec![],
+        }
+    }
+
+    fn write_line(&mut self, line: &[u8]) -> io::Result<()> {
+        if self.format == Format::Json {
+            self.out.write_all(line)?;
+            return self.out.write_all(b"\n");
+        }
+        let state: Value = serde_json::from_slice(line)?;
+        writeln!(self.out, "{}", self.format.render(self.topic, &state))
+    }
+}
+
+impl<W: Write> Write for Formatted<W> {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        self.buf.extend_from_slice(buf);
+        while let Some(end) = self.buf.iter().position(|&b| b == b'\n') {
+            let line: Vec<u8> = self.buf.drain(..=end).collect();
+            self.write_line(&line[..end])?;
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.out.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn lines_are_rendered() {
+        let format = Format::Template("{active_workspace}".parse().unwrap());
+        let mut out = Formatted::new(format, Topic::Workspaces, vec![]);
+        write!(out, "{{\"active_workspace\":1}}\n{{\"active_").unwrap();
+        writeln!(out, "workspace\":2}}").unwrap();
+        assert_eq!(String::from_utf8(out.out).unwrap(), "1\n2\n");
+    }
+}
@@ -0,0 +1,223 @@
+//! A small mustache-like template language over a listener's JSON state.
+//!
+//! - `{field}` inserts a field, `{info.1.name}` one nested in objects or arrays.
+//!   Strings are inserted as is, everything else as JSON.
+//! - `{#field}...{/field}` repeats its body for every item of an array, or
+//!   renders it once if the field is set, true, non-zero or non-empty. Inside,
+//!   `{.}` is the current item and fields are looked up on it first.
+//! - `{^field}...{/field}` renders its body only if the field isn't set.
+//! - `{{` and `}}` are literal braces.
+//!
+//! e.g. a yuck literal with a button per workspace:
+//! `(box {#workspaces}(button :onclick "nasty workspaces focus {.}" "{.}"){/workspaces})`
+
+use std::str::FromStr;
+
+use anyhow::bail;
+use serde_json::Value;
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Template(Vec<Node>);
+
+#[derive(Debug, Clone, PartialEq)]
+enum Node {
+    Text(String),
+    Field(String),
+    Section {
+        field: String,
+        inverted: bool,
+        body: Vec<Node>,
+    },
+}
+
+impl FromStr for Template {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> anyhow::Result<Template> {
+        let mut chars = s.chars().peekable();
+        // The sections being parsed, innermost last.
+        let mut open: Vec<(String, bool, Vec<Node>)> = vec![];
+        let mut nodes = vec![];
+        let mut text = String::new();
+        while let Some(c) = chars.next() {
+            match (c, chars.peek()) {
+                ('{', Some('{')) | ('}', Some('}')) => {
+                    chars.next();
+                    text.push(c);
+                }
+                ('{', _) => {
+                    let mut tag = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => tag.push(c),
+                            None => bail!("{{{tag} is never closed, use {{{{ for a literal brace"),
+                        }
+                    }
+                    if !text.is_empty() {
+                        nodes.push(Node::Text(std::mem::take(&mut text)));
+                    }
+                    match tag.chars().next() {
+                        Some(kind @ ('#' | '^')) => {
+                            let parent = std::mem::take(&mut nodes);
+                            open.push((tag[1..].trim().to_string(), kind == '^', parent));
+                        }
+                        Some('/') => {
+                            let Some((field, inverted, parent)) = open.pop() else {
+                                bail!("{{{tag}}} closes a section that isn't open");
+                            };
+                            if field != tag[1..].trim() {
+                                bail!("{{{tag}}} closes {{#{field}}}");
+                            }
+                            let body = std::mem::replace(&mut nodes, parent);
+                            nodes.push(Node::Section {
+                                field,
+                                inverted,
+                                body,
+                            });
+                        }
+                        Some(_) => nodes.push(Node::Field(tag.trim().to_string())),
+                        None => bail!("Empty {{}} in the template, use {{{{ for a literal brace"),
+                    }
+                }
+                ('}', _) => bail!("Unmatched }} in the template, use }}}} for a literal brace"),
+                _ => text.push(c),
+            }
+        }
+        if let Some((field, ..)) = open.pop() {
+            bail!("{{#{field}}} is never closed");
+        }
+        if !text.is_empty() {
+            nodes.push(Node::Text(text));
+        }
+        Ok(Template(nodes))
+    }
+}
+
+impl Template {
+    pub fn render(&self, state: &Value) -> String {
+        let mut out = String::new();
+        render(&self.0, &mut vec![state], &mut out);
+        out
+    }
+}
+
+fn render(nodes: &[Node], scopes: &mut Vec<&Value>, out: &mut String) {
+    for node in nodes {
+        match node {
+            Node::Text(text) => out.push_str(text),
+            Node::Field(field) => match lookup(field, scopes) {
+                Some(Value::String(s)) => out.push_str(s),
+                None | Some(Value::Null) => (),
+                Some(value) => out.push_str(&value.to_string()),
+            },
+            Node::Section {
+                field,
+                inverted,
+                body,
+            } => {
+                let value = lookup(field, scopes).filter(|v| is_set(v));
+                match (value, inverted) {
+                    (None, true) => render(body, scopes, out),
+                    (Some(Value::Array(items)), false) => {
+                        for item in items {
+                            scopes.push(item);
+                            render(body, scopes, out);
+                            scopes.pop();
+                        }
+                    }
+                    (Some(value), false) => {
+                        scopes.push(value);
+                        render(body, scopes, out);
+                        scopes.pop();
+                    }
+                    _ => (),
+                }
+            }
+        }
+    }
+}
+
+/// Finds `field` in the innermost scope that has its first part.
+fn lookup<'a>(field: &str, scopes: &[&'a Value]) -> Option<&'a Value> {
+    if field == "." {
+        return scopes.last().copied();
+    }
+    let mut parts = field.split('.');
+    let first = parts.next()?;
+    let mut value = scopes.iter().rev().find_map(|scope| child(scope, first))?;
+    for part in parts {
+        value = child(value, part)?;
+    }
+    Some(value)
+}
+
+fn child<'a>(value: &'a Value, key: &str) -> Option<&'a Value> {
+    match value {
+        Value::Object(map) => map.get(key),
+        Value::Array(items) => items.get(key.parse::<usize>().ok()?),
+        _ => None,
+    }
+}
+
+fn is_set(value: &Value) -> bool {
+    match value {
+        Value::Null => false,
+        Value::Bool(b) => *b,
+        Value::Number(n) => n.as_f64() != Some(0.0),
+        Value::String(s) => !s.is_empty(),
+        Value::Array(items) => !items.is_empty(),
+        Value::Object(map) => !map.is_empty(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    fn render(template: &str, state: Value) -> String {
+        template.parse::<Template>().unwrap().render(&state)
+    }
+
+    #[test]
+    fn fields_are_inserted() {
+        let state = json!({"active_workspace": 2, "info": {"2": {"name": "web"}}});
+        assert_eq!(
+            render("{{{active_workspace}: {info.2.name}{info.3.name}}}", state),
+            "{2: web}"
+        );
+    }
+
+    #[test]
+    fn sections_repeat_or_hide_their_body() {
+        let state = json!({
+            "active_workspace": 2,
+            "workspaces": [1, 2],
+            "entries": [{"id": 1, "status": "empty"}],
+            "connected": false,
+        });
+        assert_eq!(
+            render(
+                r#"(box {#workspaces}(button "{.}/{active_workspace}"){/workspaces})"#,
+                state.clone()
+            ),
+            r#"(box (button "1/2")(button "2/2"))"#
+        );
+        assert_eq!(
+            render(
+                "{#entries}{id}:{status}{/entries}{^connected} offline{/connected}",
+                state
+            ),
+            "1:empty offline"
+        );
+    }
+
+    #[test]
+    fn invalid_templates_are_errors() {
+        for template in ["{#a}", "{#a}{/b}", "{/a}", "{}", "a}", "{title", "{#a}{"] {
+            assert!(template.parse::<Template>().is_err(), "{template}");
+        }
+    }
+}
@@ -0,0 +1,283 @@
+//! Waybar's custom module protocol, see `waybar-custom(5)`.
+
+use serde::Serialize;
+use serde_json::Value;
+
+use super::Topic;
+
+#[derive(Debug, Default, PartialEq, Serialize)]
+pub struct Waybar {
+    pub text: String,
+    pub tooltip: String,
+    pub class: Vec<String>,
+}
+
+/// A string field, or the JSON of anything else.
+fn text(value: &Value) -> String {
+    match value {
+        Value::String(s) => s.clone(),
+        Value::Null => String::new(),
+        value => value.to_string(),
+    }
+}
+
+const URGENCIES: [&str; 3] = ["low", "normal", "critical"];
+
+impl Waybar {
+    pub fn new(topic: Topic, state: &Value) -> Waybar {
+        match topic {
+            Topic::Workspaces => workspaces(state),
+            Topic::Window => Waybar {
+                text: text(&state["title"]),
+                tooltip: text(&state["class"]),
+                class: match state["fullscreen"].as_bool() {
+                    Some(true) => vec!["fullscreen".into()],
+                    _ => vec![],
+                },
+            },
+            Topic::Compositor => {
+                let submap = text(&state["submap"]);
+                Waybar {
+                    text: match submap.is_empty() {
+                        true => text(&state["layout"]),
+                        false => submap.clone(),
+                    },
+                    tooltip: text(&state["layout"]),
+                    class: ["fullscreen", "floating"]
+                        .into_iter()
+                        .filter(|&key| state[key].as_bool() == Some(true))
+                        .map(String::from)
+                        .chain((!submap.is_empty()).then_some(submap))
+                        .collect(),
+                }
+            }
+            Topic::Notifications => notifications(state),
+            Topic::Updates => updates(state),
+            Topic::System => system(state),
+        }
+    }
+}
+
+/// The active workspace's name, with every workspace in the tooltip.
+fn workspaces(state: &Value) -> Waybar {
+    let name = |id: &Value| match &state["info"][text(id)]["name"] {
+        Value::String(name) => name.clone(),
+        _ => text(id),
+    };
+    let all: Vec<String> = match &state["workspaces"] {
+        Value::Array(ids) => ids.iter().map(name).collect(),
+        _ => vec![],
+    };
+    Waybar {
+        text: name(&state["active_workspace"]),
+        tooltip: all.join(" "),
+        class: match state["connected"].as_bool() {
+            Some(false) => vec!["disconnected".into()],
+            _ => vec![],
+        },
+    }
+}
+
+/// The number of notifications, by priority in the tooltip and with the
+/// most urgent one's urgency as the class.
+fn notifications(state: &Value) -> Waybar {
+    let priority = state["priority"].as_array().cloned().unwrap_or_default();
+    let notes: Vec<&Value> = priority
+        .iter()
+        .map(|id| &state["notifications"][text(id)])
+        .collect();
+    let urgency = notes
+        .first()
+        .map(|note| note["hints"]["urgency"].as_u64().unwrap_or(1) as usize);
+    Waybar {
+        text: notes.len().to_string(),
+        tooltip: notes
+            .iter()
+            .map(|note| format!("{}: {}", text(&note["app_name"]), text(&note["summary"])))
+            .collect::<Vec<_>>()
+            .join("\n"),
+        class: match urgency {
+            Some(urgency) => vec![URGENCIES.get(urgency).unwrap_or(&"critical").to_string()],
+            None => vec!["empty".into()],
+        },
+    }
+}
+
+/// The number of outdated inputs, with how far behind they are in the tooltip.
+fn updates(state: &Value) -> Waybar {
+    // The lock file couldn't be read, e.g. `[{"error": "..."}]`.
+    let Some(inputs) = state["inputs"].as_object() else {
+        let errors = state.as_array().cloned().unwrap_or_default();
+        return Waybar {
+            text: "!".into(),
+            tooltip: errors
+                .iter()
+                .map(|e| text(&e["error"]))
+                .collect::<Vec<_>>()
+                .join("\n"),
+            class: vec!["error".into()],
+        };
+    };
+    let mut outdated = 0;
+    let mut tooltip = vec![];
+    let mut class = vec![];
+    for (name, update) in inputs {
+        // Some inputs only know whether there's a newer revision.
+        let behind = match (update["ahead_by"].as_u64(), update["outdated"].as_bool()) {
+            (Some(0), _) | (_, Some(false)) => None,
+            (Some(ahead_by), _) => Some(match update["commits"][0].as_str() {
+                Some(newest) => format!("{ahead_by} commits behind, newest: {newest}"),
+                None => format!("{ahead_by} commits behind"),
+            }),
+            (_, Some(true)) => Some(format!("{} is available", text(&update["latest_rev"]))),
+            (None, None) => {
+                if !update["error"].is_null() {
+                    tooltip.push(format!("{name}: {}", text(&update["error"])));
+                    class = vec!["error".to_string()];
+                }
+                None
+            }
+        };
+        if let Some(behind) = behind {
+            outdated += 1;
+            tooltip.push(format!("{name}: {behind}"));
+        }
+    }
+    class.push(match outdated {
+        0 => "updated".into(),
+        _ => "outdated".into(),
+    });
+    Waybar {
+        text: outdated.to_string(),
+        tooltip: tooltip.join("\n"),
+        class,
+    }
+}
+
+/// The running generation's age, with whether it was built from the lock
+/// file's nixpkgs in the tooltip.
+fn system(state: &Value) -> Waybar {
+    if state.is_array() {
+        return updates(state);
+    }
+    let mut tooltip = vec![format!("NixOS {}", text(&state["nixos_version"]))];
+    if let Some(generation) = state["generation"].as_u64() {
+        tooltip.push(format!("Generation {generation}"));
+    }
+    let class = match state["matches"].as_bool() {
+        Some(true) => "updated",
+        Some(false) => {
+            tooltip.push(format!(
+                "Built from {}, the lock file has {}",
+                text(&state["rev"]),
+                text(&state["locked_rev"])
+            ));
+            "outdated"
+        }
+        None => "unknown",
+    };
+    Waybar {
+        text: match state["age_days"].as_u64() {
+            Some(days) => format!("{days}d"),
+            None => "?".into(),
+        },
+        tooltip: tooltip.join("\n"),
+        class: vec![class.into()],
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn workspaces_show_the_active_name() {
+        let state = json!({
+            "active_workspace": 2,
+            "workspaces": [1, 2],
+            "info": {"2": {"name": "web"}},
+            "connected": false,
+        });
+        assert_eq!(
+            Waybar::new(Topic::Workspaces, &state),
+            Waybar {
+                text: "web".into(),
+                tooltip: "1 web".into(),
+                class: vec!["disconnected".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn notifications_are_counted_by_priority() {
+        let state = json!({
+            "notifications": {
+                "2": {"app_name": "mail", "summary": "hi", "hints": {"urgency": 1}},
+                "3": {"app_name": "cal", "summary": "now", "hints": {"urgency": 2}},
+            },
+            "priority": [3, 2],
+            "last_id": 3,
+        });
+        assert_eq!(
+            Waybar::new(Topic::Notifications, &state),
+            Waybar {
+                text: "2".into(),
+                tooltip: "cal: now\nmail: hi".into(),
+                class: vec!["critical".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn outdated_inputs_are_counted() {
+        let state = json!({
+            "inputs": {
+                "nixpkgs": {"html_url": "https://github.com", "ahead_by": 12,
+                    "commits": ["firefox: 128.0 -> 128.0.3"]},
+                "home-manager": {"html_url": "https://github.com", "ahead_by": 0},
+                "hyprland": {"error": "Rate limited"},
+                "hyprland/aquamarine": {"html_url": "https://github.com", "ahead_by": 3},
+                "river": {"url": "", "latest_rev": "abc", "outdated": true},
+                "nasty": {"unsupported": "path inputs can't be checked for updates"},
+                // Not an input, despite its name.
+                "rate_limit": {"error": "Rate limited"},
+            },
+            "rate_limit": {"limit": 60, "remaining": 42, "reset": 1720000000},
+        });
+        assert_eq!(
+            Waybar::new(Topic::Updates, &state),
+            Waybar {
+                text: "3".into(),
+                tooltip: "hyprland: Rate limited\n\
+                    hyprland/aquamarine: 3 commits behind\n\
+                    nixpkgs: 12 commits behind, newest: firefox: 128.0 -> 128.0.3\n\
+                    rate_limit: Rate limited\n\
+                    river: abc is available"
+                    .into(),
+                class: vec!["error".into(), "outdated".into()],
+            }
+        );
+    }
+
+    #[test]
+    fn systems_behind_the_lock_file_are_outdated() {
+        let state = json!({
+            "nixos_version": "24.11.20240707.5e4fbfb", "rev": "5e4fbfb",
+            "locked_rev": "e8c38b73aeb2", "matches": false, "generation": 42,
+            "created": 1720310400, "age_days": 12,
+        });
+        assert_eq!(
+            Waybar::new(Topic::System, &state),
+            Waybar {
+                text: "12d".into(),
+                tooltip: "NixOS 24.11.20240707.5e4fbfb\n\
+                    Generation 42\n\
+                    Built from 5e4fbfb, the lock file has e8c38b73aeb2"
+                    .into(),
+                class: vec!["outdated".into()],
+            }
+        );
+    }
+}
@@ -0,0 +1,141 @@
+//! Checks kept on disk, in `$XDG_CACHE_HOME/nasty`, so that running
+//! `nasty updates` again doesn't count against the forges' rate limits.
+
+use std::env;
+use std::fs;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Duration;
+
+use serde::{Deserialize, Serialize};
+
+use super::{unix_now, Update};
+
+/// Where checks are cached by default.
+pub(super) fn dir() -> PathBuf {
+    let dir = match env::var("XDG_CACHE_HOME") {
+        Ok(dir) if !dir.is_empty() => PathBuf::from(dir),
+        _ => Path::new(&env::var("HOME").unwrap_or_default()).join(".cache"),
+    };
+    dir.join("nasty")
+}
+
+#[derive(Debug)]
+pub(super) struct Cache {
+    dir: PathBuf,
+    /// How long an entry is used without asking whether it changed.
+    ttl: Duration,
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub(super) struct Entry {
+    /// Makes asking whether it changed free, a `304` doesn't count against
+    /// GitHub's rate limit.
+    pub etag: Option<String>,
+    /// When it was last fetched or confirmed, as a unix timestamp.
+    pub fetched: u64,
+    pub update: Update,
+}
+
+impl Cache {
+    pub fn new(dir: PathBuf, ttl: Duration) -> Cache {
+        Cache { dir, ttl }
+    }
+
+    /// The entry for `key`, e.g. the repo, locked revision and followed ref.
+    pub fn get(&self, key: &[&str]) -> Option<Entry> {
+        let contents = fs::read_to_string(self.path(key)).ok()?;
+        serde_json::from_str(&contents).ok()
+    }
+
+    /// Saves an entry, a failure only costs a request next time.
+    pub fn put(&self, key: &[&str], entry: &Entry) {
+        if let Err(e) = self.write(key, entry) {
+            eprintln!("Not caching {}: {e}", self.path(key).display());
+        }
+    }
+
+    /// Whether the entry can be used without asking the forge.
+    pub fn is_fresh(&self, entry: &Entry) -> bool {
+        unix_now().saturating_sub(entry.fetched) < self.ttl.as_secs()
+    }
+
+    fn write(&self, key: &[&str], entry: &Entry) -> io::Result<()> {
+        fs::create_dir_all(&self.dir)?;
+        // Concurrent runs, and checks within one, never see half an entry.
+        static WRITES: AtomicU64 = AtomicU64::new(0);
+        let tmp = self.dir.join(format!(
+            "{}.{}.{}.tmp",
+            name(key),
+            std::process::id(),
+            WRITES.fetch_add(1, Ordering::Relaxed)
+        ));
+        fs::write(&tmp, serde_json::to_string(entry)?)?;
+        fs::rename(tmp, self.path(key))
+    }
+
+    fn path(&self, key: &[&str]) -> PathBuf {
+        self.dir.join(format!("{}.json", name(key)))
+    }
+}
+
+/// A file name for `key`, the parts joined by `_`. Any other byte is escaped
+/// as `%XX`, so that every key gets its own file.
+fn name(key: &[&str]) -> String {
+    let escape = |part: &str| {
+        part.bytes()
+            .map(|b| match b {
+                b'a'..=b'z' | b'A'..=b'Z' | b'0'..=b'9' | b'-' | b'.' => (b as char).to_string(),
+                _ => format!("%{b:02X}"),
+            })
+            .collect::<String>()
+    };
+    key.iter()
+        .map(|part| escape(part))
+        .collect::<Vec<_>>()
+        .join("_")
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn entries_are_kept_per_key() {
+        let dir = env::temp_dir().join(format!("nasty-cache-entries-{}", std::process::id()));
+        let cache = Cache::new(dir.clone(), Duration::from_secs(60));
+        let key = ["github", "nixos", "nixpkgs", "abc", "nixos-unstable"];
+        assert_eq!(cache.get(&key), None);
+
+        let entry = Entry {
+            etag: Some("\"etag\"".to_string()),
+            fetched: unix_now(),
+            update: Update::Error {
+                error: "cached".to_string(),
+            },
+        };
+        cache.put(&key, &entry);
+        assert_eq!(cache.get(&key), Some(entry.clone()));
+        assert!(cache.is_fresh(&entry));
+        // Another ref, e.g. `release/24.05`, is another entry.
+        assert_eq!(
+            cache.get(&["github", "nixos", "nixpkgs", "abc", "release/24.05"]),
+            None
+        );
+
+        // Keys that only differ in escaped characters don't share a file.
+        assert_eq!(cache.get(&["a/b", "c"]), None);
+        cache.put(&["a_b", "c"], &entry);
+        assert_eq!(cache.get(&["a/b", "c"]), None);
+        assert_eq!(name(&["a/b", "c"]), "a%2Fb_c");
+        assert_eq!(name(&["a_b", "c"]), "a%5Fb_c");
+
+        let old = Entry {
+            fetched: unix_now() - 61,
+            ..entry
+        };
+        assert!(!cache.is_fresh(&old));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
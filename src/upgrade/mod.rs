@@ -0,0 +1,2653 @@
+use std::collections::{BTreeMap, HashMap, VecDeque};
+use std::env;
+use std::fs;
+use std::io::{self, Write};
+use std::path::Path;
+use std::process::Output;
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use futures::future::BoxFuture;
+use futures::{FutureExt, StreamExt};
+use reqwest::header::{HeaderMap, ETAG, IF_NONE_MATCH, LAST_MODIFIED};
+use reqwest::{Response, StatusCode};
+use serde::{Deserialize, Serialize};
+use tokio::process::Command;
+use tokio::sync::{mpsc, watch};
+use tokio::time::Instant;
+
+use crate::config;
+use cache::{Cache, Entry};
+
+mod cache;
+pub mod system;
+
+/// How many inputs are checked at once.
+const MAX_CONCURRENT_CHECKS: usize = 8;
+
+/// How long a single request or command may take, so one slow forge doesn't
+/// hold up every other input.
+const TIMEOUT: Duration = Duration::from_secs(20);
+
+/// Writes update information on a NixOS system to `out`.
+pub async fn nixos(settings: &config::Updates, out: &mut impl Write) -> io::Result<()> {
+    let ctx = Context::new(settings).await.map_err(io::Error::other)?;
+    writeln!(out, "{}", check_nixos(settings, &ctx).await)
+}
+
+/// Writes update information on a NixOS system to `out` every
+/// `updates.interval`, whenever the lock file is written and whenever
+/// `[updates]` changes, but only when it changed.
+///
+/// Checks are put off until GitHub's rate limit resets.
+pub async fn watch_nixos(
+    mut config: watch::Receiver<config::Config>,
+    flags: &config::UpdatesFlags,
+    out: &mut impl Write,
+) -> io::Result<()> {
+    let mut settings = flags.apply(&config.borrow_and_update().updates);
+    let mut ctx = Context::new(&settings).await.map_err(io::Error::other)?;
+    let mut written = watch_lock_file(&settings);
+    let mut last = None;
+    loop {
+        let updates = check_nixos(&settings, &ctx).await;
+        if last.as_ref() != Some(&updates) {
+            writeln!(out, "{updates}")?;
+            out.flush()?;
+            last = Some(updates);
+        }
+        let next = Instant::now() + ctx.next_check(Duration::from_secs(settings.interval));
+        loop {
+            tokio::select! {
+                _ = tokio::time::sleep_until(next) => break,
+                Some(()) = written.recv() => break,
+                Ok(()) = config.changed() => {
+                    let updated = flags.apply(&config.borrow_and_update().updates);
+                    if updated == settings {
+                        continue;
+                    }
+                    let reset = ctx.rate_limited();
+                    ctx = Context::new(&updated).await.map_err(io::Error::other)?;
+                    if let Some(reset) = reset {
+                        ctx.rate_limited_until(reset);
+                    }
+                    if updated.lock_file != settings.lock_file {
+                        written = watch_lock_file(&updated);
+                    }
+                    settings = updated;
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Receives a message whenever the lock file is written, one for all the writes
+/// in between checks.
+fn watch_lock_file(settings: &config::Updates) -> mpsc::Receiver<()> {
+    let (tx, written) = mpsc::channel(1);
+    let file_path = &settings.lock_file;
+    if let Err(e) = config::on_write(Path::new(file_path), move || {
+        let _ = tx.try_send(());
+    }) {
+        eprintln!(
+            "Not watching {file_path} ({e}), checking every {}s.",
+            settings.interval
+        );
+    }
+    written
+}
+
+/// Update information on a NixOS system, as JSON.
+///
+/// Only the root's inputs are checked unless `transitive` is set.
+async fn check_nixos(settings: &config::Updates, ctx: &Context) -> String {
+    // Read Nix lockfile
+    let contents = match read_lockfile(&settings.lock_file) {
+        Ok(contents) => contents,
+        Err(error) => return serde_json::json!([{ "error": error }]).to_string(),
+    };
+    match contents.nodes.get(&contents.root) {
+        Some(NodeType::Root { .. }) => (),
+        Some(NodeType::Node { .. }) => {
+            return r#"[{"error": "Unable to find inputs on root node"}]"#.to_string();
+        }
+        None => {
+            return r#"[{"error": "Unable to find root node"}]"#.to_string();
+        }
+    };
+
+    // Check for updates
+    let mut checks = vec![];
+    for (node, reached) in contents.walk(settings.transitive) {
+        if let Some(NodeType::Node {
+            original, locked, ..
+        }) = contents.nodes.get(node)
+        {
+            checks.push(check_input(ctx, reached, original, locked));
+        }
+    }
+    let inputs = futures::stream::iter(checks)
+        .buffer_unordered(MAX_CONCURRENT_CHECKS)
+        .collect()
+        .await;
+    let updates = Updates {
+        inputs,
+        rate_limit: *ctx.github_rate_limit.lock().unwrap(),
+    };
+
+    serde_json::to_string(&updates).unwrap_or("{\"error\": \"idk man\"}".to_string())
+}
+
+/// Checks a single input, keyed by its name.
+async fn check_input(
+    ctx: &Context,
+    reached: Reached,
+    original: &FlakeRef,
+    locked: &Locked,
+) -> (String, Report) {
+    let (_ref, pinned) = original.followed();
+    let update = checker_factory(locked, original, ctx.use_gh)
+        .check(ctx)
+        .await;
+    let report = Report {
+        update,
+        source: original.name(),
+        rev: locked.source.followed().1.map(str::to_string),
+        _ref: pinned.or(_ref).map(str::to_string),
+        last_modified: locked.last_modified,
+        age_days: locked.last_modified.map(days_since),
+        via: reached.via,
+    };
+    (reached.name, report)
+}
+
+/// Whole days from a unix timestamp until now.
+fn days_since(timestamp: u64) -> u64 {
+    unix_now().saturating_sub(timestamp) / (24 * 60 * 60)
+}
+
+fn unix_now() -> u64 {
+    SystemTime::now()
+        .duration_since(UNIX_EPOCH)
+        .expect("Time went backwards")
+        .as_secs()
+}
+
+/// Every input's report, keyed by name.
+#[derive(Debug, Serialize)]
+struct Updates {
+    /// Nested, so no input's name can clash with the other fields.
+    inputs: BTreeMap<String, Report>,
+    /// GitHub's quota as of its latest response.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rate_limit: Option<RateLimit>,
+}
+
+/// An input's update, with where it's locked to and the inputs that pull it in.
+#[derive(Debug, Serialize)]
+struct Report {
+    #[serde(flatten)]
+    update: Update,
+    /// A short name for the source, e.g. `nixos/nixpkgs`.
+    source: String,
+    /// The locked revision.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+    /// The branch, tag or revision followed, the default branch if unset.
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    _ref: Option<String>,
+    /// When the locked revision was last modified, as a unix timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    age_days: Option<u64>,
+    /// Paths of the inputs depending on it, e.g. `hyprland/aquamarine`.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    via: Vec<String>,
+}
+
+/// What every check shares.
+struct Context {
+    client: reqwest::Client,
+    /// Whether the GitHub CLI is present and authenticated.
+    use_gh: bool,
+    /// How long a single request or command may take.
+    timeout: Duration,
+    /// When the latest rate limit resets, as a unix timestamp.
+    rate_limit_reset: Mutex<Option<u64>>,
+    cache: Cache,
+    /// e.g. `https://api.github.com`
+    github_api: String,
+    github_token: Option<String>,
+    /// GitHub's quota as of its latest response.
+    github_rate_limit: Mutex<Option<RateLimit>>,
+    /// e.g. `https://channels.nixos.org`
+    channels_url: String,
+    sourcehut_token: Option<String>,
+}
+
+/// The GitHub token from `GITHUB_TOKEN`, `GH_TOKEN`, the config's `github_token`
+/// or the file at `github_token_file`, the first one that's set.
+fn github_token(
+    var: impl Fn(&str) -> Option<String>,
+    settings: &config::Updates,
+) -> Option<String> {
+    let set = [
+        var("GITHUB_TOKEN"),
+        var("GH_TOKEN"),
+        settings.github_token.clone(),
+    ];
+    first_token(set, settings.github_token_file.as_deref(), "GitHub")
+}
+
+/// The sourcehut token from `SOURCEHUT_TOKEN`, the config's `sourcehut_token`
+/// or the file at `sourcehut_token_file`, the first one that's set.
+fn sourcehut_token(
+    var: impl Fn(&str) -> Option<String>,
+    settings: &config::Updates,
+) -> Option<String> {
+    let set = [var("SOURCEHUT_TOKEN"), settings.sourcehut_token.clone()];
+    first_token(set, settings.sourcehut_token_file.as_deref(), "sourcehut")
+}
+
+/// The first non-empty token, otherwise the one in `file`.
+fn first_token(
+    tokens: impl IntoIterator<Item = Option<String>>,
+    file: Option<&str>,
+    forge: &str,
+) -> Option<String> {
+    let from_file = || match fs::read_to_string(file?) {
+        Ok(token) => Some(token.trim().to_string()),
+        Err(e) => {
+            eprintln!("Not using the {forge} token file: {e}");
+            None
+        }
+    };
+    tokens
+        .into_iter()
+        .flatten()
+        .find(|t| !t.is_empty())
+        .or_else(from_file)
+        .filter(|t| !t.is_empty())
+}
+
+/// A forge's quota, from the `x-ratelimit-*` headers of a response.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+struct RateLimit {
+    limit: u64,
+    remaining: u64,
+    /// When it's replenished, as a unix timestamp.
+    reset: u64,
+}
+
+impl RateLimit {
+    fn from_headers(headers: &HeaderMap) -> Option<RateLimit> {
+        let header = |name: &str| headers.get(name)?.to_str().ok()?.parse().ok();
+        Some(RateLimit {
+            limit: header("x-ratelimit-limit")?,
+            remaining: header("x-ratelimit-remaining")?,
+            reset: header("x-ratelimit-reset")?,
+        })
+    }
+}
+
+impl Context {
+    async fn new(settings: &config::Updates) -> reqwest::Result<Context> {
+        let client = reqwest::Client::builder()
+            .user_agent("KGB33/nasty")
+            .timeout(TIMEOUT)
+            .build()?;
+        let mut ctx = Context {
+            client,
+            use_gh: false,
+            timeout: TIMEOUT,
+            rate_limit_reset: Mutex::new(None),
+            cache: Cache::new(cache::dir(), Duration::from_secs(settings.cache_ttl)),
+            github_api: "https://api.github.com".to_string(),
+            github_token: github_token(|var| env::var(var).ok(), settings),
+            github_rate_limit: Mutex::new(None),
+            channels_url: settings.channels_url.trim_end_matches('/').to_string(),
+            sourcehut_token: sourcehut_token(|var| env::var(var).ok(), settings),
+        };
+        // A token makes HTTP requests as good as the CLI's, and they're cached.
+        if ctx.github_token.is_none() {
+            ctx.use_gh = Github::gh_cli_is_ready(&ctx).await;
+        }
+        Ok(ctx)
+    }
+
+    /// Runs a command, killing it once it takes longer than the timeout.
+    async fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.kill_on_drop(true);
+        match tokio::time::timeout(self.timeout, cmd.output()).await {
+            Ok(out) => out,
+            Err(_) => Err(io::Error::new(io::ErrorKind::TimedOut, self.timed_out())),
+        }
+    }
+
+    /// Notes that no more requests should be made until `reset`.
+    fn rate_limited_until(&self, reset: u64) {
+        let mut current = self.rate_limit_reset.lock().unwrap();
+        *current = Some(current.map_or(reset, |c| c.max(reset)));
+    }
+
+    /// When the rate limit resets, if it hasn't yet.
+    fn rate_limited(&self) -> Option<u64> {
+        let mut reset = self.rate_limit_reset.lock().unwrap();
+        *reset = reset.filter(|&r| r > unix_now());
+        *reset
+    }
+
+    /// How long to wait before checking again, at least until a rate limit
+    /// resets.
+    fn next_check(&self, interval: Duration) -> Duration {
+        let until_reset = self
+            .rate_limited()
+            .map_or(0, |r| r.saturating_sub(unix_now()));
+        interval.max(Duration::from_secs(until_reset))
+    }
+
+    fn timed_out(&self) -> String {
+        format!("Timed out after {}s", self.timeout.as_secs_f32())
+    }
+
+    /// Describes a failed request, naming the timeout rather than just the url.
+    fn describe(&self, e: reqwest::Error) -> String {
+        match e.is_timeout() {
+            true => self.timed_out(),
+            false => e.to_string(),
+        }
+    }
+}
+
+trait Checker: Send + Sync {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update>;
+}
+
+/// Picks a checker for where the input is locked to, following what the
+/// input originally referred to, e.g. a branch.
+fn checker_factory<'a>(
+    lock: &'a Locked,
+    orig: &'a FlakeRef,
+    use_gh: bool,
+) -> Box<dyn Checker + 'a> {
+    let (_ref, pinned) = orig.followed();
+    match &lock.source {
+        FlakeRef::GitHub(repo) if pinned.is_none() && Channel::is_channel(repo, _ref) => {
+            Box::new(Channel {
+                use_gh,
+                repo,
+                channel: _ref.unwrap_or_default(),
+                last_modified: lock.last_modified,
+            })
+        }
+        FlakeRef::GitHub(repo) => Box::new(Github {
+            use_cli: use_gh,
+            repo,
+            _ref: pinned.or(_ref),
+        }),
+        FlakeRef::GitLab(repo) => Box::new(Gitlab::new(repo, _ref, pinned)),
+        FlakeRef::SourceHut(repo) => Box::new(Sourcehut::new(repo, _ref, pinned)),
+        FlakeRef::Git { url, rev, .. } => Box::new(GitRemote {
+            url: url.clone(),
+            rev: rev.as_deref(),
+            _ref,
+            pinned,
+        }),
+        source => Box::new(Unsupported(source.kind())),
+    }
+}
+
+struct Github<'a> {
+    use_cli: bool,
+    /// Where the input is locked to.
+    repo: &'a Repo,
+    /// The branch, tag or revision the input follows, the default branch if unset.
+    _ref: Option<&'a str>,
+}
+
+impl Checker for Github<'_> {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update> {
+        async move {
+            let key = self.cache_key();
+            let cached = ctx.cache.get(&key);
+            if let Some(entry) = cached.as_ref().filter(|e| ctx.cache.is_fresh(e)) {
+                return entry.update.clone();
+            }
+            if self.use_cli {
+                let update = self.check_using_cli(ctx).await;
+                if let Update::Valid { .. } = update {
+                    let entry = Entry {
+                        etag: None,
+                        fetched: unix_now(),
+                        update: update.clone(),
+                    };
+                    ctx.cache.put(&key, &entry);
+                }
+                return update;
+            }
+            self.check_using_http(ctx, &key, cached).await
+        }
+        .boxed()
+    }
+}
+
+impl Github<'_> {
+    /// Checks to see if the GitHub CLI is prestent and authenticated.
+    async fn gh_cli_is_ready(ctx: &Context) -> bool {
+        let mut cmd = Command::new("gh");
+        let cmd = cmd.arg("auth").arg("status");
+        let output = match ctx.output(cmd).await {
+            Ok(o) => o,
+            Err(_) => return false, // GH CLI isn't callable
+        };
+        output.status.code() == Some(0)
+    }
+
+    /// Checks are cached per repo, locked revision and followed ref.
+    fn cache_key(&self) -> [&str; 5] {
+        [
+            "github",
+            &self.repo.owner,
+            &self.repo.repo,
+            self.repo.rev.as_deref().unwrap_or_default(),
+            self._ref.unwrap_or("HEAD"),
+        ]
+    }
+
+    fn generate_enpoint(&self) -> String {
+        // /repos/{onwer}/{repo}/compare/{}...{_ref/HEAD}
+        // If the _ref isn't incluseed replace with HEAD
+        let _ref = self._ref.unwrap_or("HEAD");
+        let rev = self.repo.rev.as_deref().unwrap_or_default();
+        format!(
+            "repos/{}/{}/compare/{}...{}",
+            self.repo.owner, self.repo.repo, rev, _ref
+        )
+    }
+
+    async fn check_using_cli(&self, ctx: &Context) -> Update {
+        let endpoint = self.generate_enpoint();
+        let mut cmd = Command::new("gh");
+        let cmd = cmd.arg("api").arg(endpoint);
+        let out = match ctx.output(cmd).await {
+            Ok(o) => o,
+            Err(e) => {
+                return Update::Error {
+                    error: e.to_string(),
+                }
+            }
+        };
+        if out.status.code() != Some(0) {
+            return Update::Error {
+                error: String::from_utf8(out.stderr)
+                    .unwrap_or("Command failed, and unwrapping srderr failled too!".to_string()),
+            };
+        };
+        let response = match String::from_utf8(out.stdout) {
+            Ok(r) => r,
+            Err(e) => {
+                return Update::Error {
+                    error: e.to_string(),
+                }
+            }
+        };
+        match serde_json::from_str::<GithubCompare>(&response) {
+            Ok(compare) => compare.into(),
+            Err(_) => Update::Error {
+                error: String::from("Falled to parse response to json."),
+            },
+        }
+    }
+
+    /// Asks for the comparison, unless it's still `etag`.
+    async fn send(&self, ctx: &Context, etag: Option<&str>) -> reqwest::Result<Response> {
+        let source_url = format!("{}/{}", ctx.github_api, self.generate_enpoint());
+        let mut request = ctx.client.get(source_url);
+        if let Some(etag) = etag {
+            request = request.header(IF_NONE_MATCH, etag);
+        }
+        if let Some(token) = &ctx.github_token {
+            request = request.bearer_auth(token);
+        }
+        let resp = request.send().await?;
+        if let Some(limit) = RateLimit::from_headers(resp.headers()) {
+            *ctx.github_rate_limit.lock().unwrap() = Some(limit);
+            // The next request would be rate limited, so don't make it.
+            if limit.remaining == 0 {
+                ctx.rate_limited_until(limit.reset);
+            }
+        }
+        Ok(resp)
+    }
+
+    async fn check_using_http(&self, ctx: &Context, key: &[&str], cached: Option<Entry>) -> Update {
+        // Written lock files and the other checks of a pass don't wait for the
+        // reset on their own.
+        if let Some(reset) = ctx.rate_limited() {
+            return match cached {
+                Some(entry) => entry.update.into_stale(),
+                None => Update::Error {
+                    error: format!(
+                        "Rate limited... try again in {}s",
+                        reset.saturating_sub(unix_now())
+                    ),
+                },
+            };
+        }
+        let etag = cached.as_ref().and_then(|e| e.etag.as_deref());
+        let mut resp = match self.send(ctx, etag).await {
+            Ok(resp) => resp,
+            Err(e) => {
+                return Update::Error {
+                    error: ctx.describe(e),
+                }
+            }
+        };
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            match &cached {
+                Some(entry) => {
+                    let entry = Entry {
+                        fetched: unix_now(),
+                        ..entry.clone()
+                    };
+                    ctx.cache.put(key, &entry);
+                    return entry.update;
+                }
+                // There's nothing to reuse, so ask for the whole comparison.
+                None => match self.send(ctx, None).await {
+                    Ok(retried) => resp = retried,
+                    Err(e) => {
+                        return Update::Error {
+                            error: ctx.describe(e),
+                        }
+                    }
+                },
+            }
+        }
+        self.read_response(ctx, key, resp, cached).await
+    }
+
+    /// The update in a response that isn't a `304`.
+    async fn read_response(
+        &self,
+        ctx: &Context,
+        key: &[&str],
+        resp: Response,
+        cached: Option<Entry>,
+    ) -> Update {
+        if resp.status() == StatusCode::NOT_MODIFIED {
+            return Update::Error {
+                error: "GitHub answered 304 Not Modified without an ETag to compare with"
+                    .to_string(),
+            };
+        }
+        if resp.status() == StatusCode::FORBIDDEN {
+            // x-ratelimit-reset
+            let ratelimit_reset: i64 = match resp.headers().get("x-ratelimit-reset") {
+            Some(r) => match r.to_str() {
+                Ok(s) => match s.parse::<i64>() {
+                    Ok(i) => i,
+                    Err(e) => {
+                        return Update::Error {
+                            error: e.to_string(),
+                        }
+                    }
+                },
+                Err(e) => {
+                    return Update::Error {
+                        error: e.to_string(),
+                    }
+                }
+            },
+            None => return Update::Error {
+                error:
+                    "You appear to be ratelimited but the 'x-ratelimit-reset' header isn't present."
+                        .to_string(),
+            },
+        };
+            let time_til_unlimited = unix_now() as i64;
+            ctx.rate_limited_until(ratelimit_reset as u64);
+            if let Some(entry) = cached {
+                return entry.update.into_stale();
+            }
+            return Update::Error {
+                error: format!(
+                    "Rate limited... try again in {}s",
+                    ratelimit_reset - time_til_unlimited
+                ),
+            };
+        };
+        let etag = resp
+            .headers()
+            .get(ETAG)
+            .and_then(|e| e.to_str().ok())
+            .map(str::to_string);
+        match resp.json::<GithubCompare>().await {
+            Ok(compare) => {
+                let entry = Entry {
+                    etag,
+                    fetched: unix_now(),
+                    update: compare.into(),
+                };
+                ctx.cache.put(key, &entry);
+                entry.update
+            }
+            Err(e) => Update::Error {
+                error: ctx.describe(e),
+            },
+        }
+    }
+}
+
+/// The parts of GitHub's compare response that are reported.
+#[derive(Debug, Deserialize)]
+struct GithubCompare {
+    html_url: String,
+    ahead_by: u64,
+    behind_by: u64,
+    /// Oldest first, at most 250 of them.
+    commits: Vec<GithubCommit>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubCommit {
+    commit: GithubCommitDetails,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubCommitDetails {
+    message: String,
+    committer: Option<GithubSignature>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GithubSignature {
+    date: String,
+}
+
+impl From<GithubCompare> for Update {
+    fn from(compare: GithubCompare) -> Self {
+        let newest_commit_date = compare
+            .commits
+            .last()
+            .and_then(|c| c.commit.committer.as_ref())
+            .map(|c| c.date.clone());
+        Update::Valid {
+            html_url: compare.html_url,
+            stale: false,
+            ahead_by: compare.ahead_by,
+            behind_by: Some(compare.behind_by),
+            newest_commit_date,
+            commits: latest_messages(compare.commits.iter().map(|c| c.commit.message.as_str())),
+        }
+    }
+}
+
+/// How many commit messages are reported.
+const LATEST_COMMITS: usize = 5;
+
+/// The first lines of the last `LATEST_COMMITS` messages of commits listed
+/// oldest first, newest first.
+fn latest_messages<'a>(messages: impl DoubleEndedIterator<Item = &'a str>) -> Vec<String> {
+    messages
+        .rev()
+        .take(LATEST_COMMITS)
+        .map(|m| m.lines().next().unwrap_or_default().to_string())
+        .collect()
+}
+
+/// Compares the locked revision with the followed branch using the compare
+/// API of gitlab.com or a self-hosted instance.
+struct Gitlab<'a> {
+    /// e.g. `https://gitlab.freedesktop.org/api/v4`
+    api_url: String,
+    repo: &'a Repo,
+    _ref: Option<&'a str>,
+    pinned: Option<&'a str>,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabProject {
+    default_branch: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabCompare {
+    /// Oldest first.
+    commits: Vec<GitlabCommit>,
+    web_url: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct GitlabCommit {
+    title: String,
+    committed_date: String,
+}
+
+impl Checker for Gitlab<'_> {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update> {
+        async move {
+            self.compare(ctx)
+                .await
+                .unwrap_or_else(|error| Update::Error { error })
+        }
+        .boxed()
+    }
+}
+
+impl Gitlab<'_> {
+    fn new<'a>(repo: &'a Repo, _ref: Option<&'a str>, pinned: Option<&'a str>) -> Gitlab<'a> {
+        let host = repo.host.as_deref().unwrap_or("gitlab.com");
+        Gitlab {
+            api_url: format!("https://{host}/api/v4"),
+            repo,
+            _ref,
+            pinned,
+        }
+    }
+
+    /// `projects/{id}`, the id being the url encoded path of the project.
+    fn project_endpoint(&self) -> String {
+        let path = format!("{}/{}", self.repo.owner, self.repo.repo);
+        format!("{}/projects/{}", self.api_url, path.replace('/', "%2F"))
+    }
+
+    async fn get<T: for<'de> Deserialize<'de>>(
+        &self,
+        ctx: &Context,
+        url: &str,
+    ) -> Result<T, String> {
+        let resp = ctx
+            .client
+            .get(url)
+            .send()
+            .await
+            .map_err(|e| ctx.describe(e))?;
+        if resp.status() == StatusCode::TOO_MANY_REQUESTS {
+            let retry_after = resp
+                .headers()
+                .get("retry-after")
+                .and_then(|r| r.to_str().ok())
+                .unwrap_or("?");
+            if let Ok(secs) = retry_after.parse::<u64>() {
+                ctx.rate_limited_until(unix_now() + secs);
+            }
+            return Err(format!("Rate limited... try again in {retry_after}s"));
+        }
+        if !resp.status().is_success() {
+            return Err(format!("{url} returned {}", resp.status()));
+        }
+        resp.json::<T>().await.map_err(|e| ctx.describe(e))
+    }
+
+    async fn compare(&self, ctx: &Context) -> Result<Update, String> {
+        let Some(from) = self.repo.rev.as_deref() else {
+            return Err("The input isn't locked to a revision".to_string());
+        };
+        // Inputs pinned to a revision never update, otherwise they follow a
+        // branch or the default branch.
+        let to = match (self.pinned, self._ref) {
+            (Some(rev), _) => rev.to_string(),
+            (None, Some(_ref)) => _ref.to_string(),
+            (None, None) => {
+                self.get::<GitlabProject>(ctx, &self.project_endpoint())
+                    .await?
+                    .default_branch
+            }
+        };
+        let url = format!("{}/repository/compare", self.project_endpoint());
+        let mut url = reqwest::Url::parse(&url).map_err(|e| format!("{url}: {e}"))?;
+        url.query_pairs_mut()
+            .append_pair("from", from)
+            .append_pair("to", &to);
+        let compare = self.get::<GitlabCompare>(ctx, url.as_str()).await?;
+        Ok(Update::Valid {
+            html_url: compare.web_url,
+            stale: false,
+            ahead_by: compare.commits.len() as u64,
+            // Only commits missing from the lock are listed.
+            behind_by: None,
+            newest_commit_date: compare.commits.last().map(|c| c.committed_date.clone()),
+            commits: latest_messages(compare.commits.iter().map(|c| c.title.as_str())),
+        })
+    }
+}
+
+/// Walks the log of the followed branch back to the locked revision using the
+/// GraphQL API of git.sr.ht or a self-hosted instance, which needs a token.
+/// Without one, it's asked for the latest revision with git.
+struct Sourcehut<'a> {
+    /// e.g. `https://git.sr.ht`
+    url: String,
+    /// e.g. `https://git.sr.ht/query`
+    api_url: String,
+    repo: &'a Repo,
+    _ref: Option<&'a str>,
+    pinned: Option<&'a str>,
+}
+
+/// How many pages of the log are walked looking for the locked revision.
+const SOURCEHUT_MAX_PAGES: usize = 20;
+
+const SOURCEHUT_LOG_QUERY: &str = "query log($owner: String!, $repo: String!, \
+    $from: String, $cursor: Cursor) { user(username: $owner) { repository(name: $repo) { \
+    log(from: $from, cursor: $cursor) { results { id title committer { time } } cursor } } } }";
+
+#[derive(Debug, Deserialize)]
+struct SourcehutLog {
+    /// Newest first.
+    results: Vec<SourcehutCommit>,
+    /// Where the next page starts, if there is one.
+    cursor: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourcehutCommit {
+    id: String,
+    title: String,
+    committer: SourcehutSignature,
+}
+
+#[derive(Debug, Deserialize)]
+struct SourcehutSignature {
+    time: String,
+}
+
+impl Checker for Sourcehut<'_> {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update> {
+        async move {
+            let Some(token) = &ctx.sourcehut_token else {
+                let remote = GitRemote {
+                    url: self.url.clone(),
+                    rev: self.repo.rev.as_deref(),
+                    _ref: self._ref,
+                    pinned: self.pinned,
+                };
+                return remote.check(ctx).await;
+            };
+            self.compare(ctx, token)
+                .await
+                .unwrap_or_else(|error| Update::Error { error })
+        }
+        .boxed()
+    }
+}
+
+impl Sourcehut<'_> {
+    fn new<'a>(repo: &'a Repo, _ref: Option<&'a str>, pinned: Option<&'a str>) -> Sourcehut<'a> {
+        let host = repo.host.as_deref().unwrap_or("git.sr.ht");
+        Sourcehut {
+            url: format!("https://{host}/{}/{}", repo.owner, repo.repo),
+            api_url: format!("https://{host}/query"),
+            repo,
+            _ref,
+            pinned,
+        }
+    }
+
+    /// A page of the log, starting at `from` or `HEAD`.
+    async fn log(
+        &self,
+        ctx: &Context,
+        token: &str,
+        from: Option<&str>,
+        cursor: Option<String>,
+    ) -> Result<SourcehutLog, String> {
+        let query = serde_json::json!({
+            "query": SOURCEHUT_LOG_QUERY,
+            "variables": {
+                "owner": self.repo.owner.trim_start_matches('~'),
+                "repo": self.repo.repo,
+                "from": from,
+                "cursor": cursor,
+            },
+        });
+        let resp = ctx
+            .client
+            .post(&self.api_url)
+            .bearer_auth(token)
+            .json(&query)
+            .send()
+            .await
+            .map_err(|e| ctx.describe(e))?;
+        if !resp.status().is_success() {
+            return Err(format!("{} returned {}", self.api_url, resp.status()));
+        }
+        let body: serde_json::Value = resp.json().await.map_err(|e| ctx.describe(e))?;
+        if let Some(error) = body.pointer("/errors/0/message").and_then(|m| m.as_str()) {
+            return Err(error.to_string());
+        }
+        match body.pointer("/data/user/repository/log") {
+            Some(log) if !log.is_null() => {
+                serde_json::from_value(log.clone()).map_err(|e| e.to_string())
+            }
+            _ => Err(format!("{} doesn't exist", self.url)),
+        }
+    }
+
+    async fn compare(&self, ctx: &Context, token: &str) -> Result<Update, String> {
+        let Some(locked) = self.repo.rev.as_deref() else {
+            return Err("The input isn't locked to a revision".to_string());
+        };
+        // Inputs pinned to a revision never update, otherwise they follow a
+        // branch or the default branch.
+        let from = self.pinned.or(self._ref);
+        // Newest first.
+        let mut missing = vec![];
+        let mut cursor = None;
+        for _ in 0..SOURCEHUT_MAX_PAGES {
+            let log = self.log(ctx, token, from, cursor).await?;
+            for commit in log.results {
+                if commit.id == locked {
+                    return Ok(Update::Valid {
+                        html_url: format!("{}/log/{}", self.url, from.unwrap_or("HEAD")),
+                        stale: false,
+                        ahead_by: missing.len() as u64,
+                        // Only commits missing from the lock are listed.
+                        behind_by: None,
+                        newest_commit_date: missing
+                            .first()
+                            .map(|c: &SourcehutCommit| c.committer.time.clone()),
+                        commits: latest_messages(missing.iter().rev().map(|c| c.title.as_str())),
+                    });
+                }
+                missing.push(commit);
+            }
+            cursor = match log.cursor {
+                Some(cursor) => Some(cursor),
+                None => {
+                    let from = from.unwrap_or("HEAD");
+                    return Err(format!("{locked} isn't on {from}"));
+                }
+            };
+        }
+        Err(format!(
+            "{locked} is more than {} commits behind",
+            missing.len()
+        ))
+    }
+}
+
+/// Compares nixpkgs inputs following a channel, e.g. `nixos-unstable`, with
+/// the revision the channel is at. The branch is ahead of the channel until
+/// Hydra has built it, so comparing with it overstates how outdated they are.
+struct Channel<'a> {
+    use_gh: bool,
+    repo: &'a Repo,
+    channel: &'a str,
+    /// When the locked revision was committed, as a unix timestamp.
+    last_modified: Option<u64>,
+}
+
+impl Checker for Channel<'_> {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update> {
+        async move {
+            self.compare(ctx)
+                .await
+                .unwrap_or_else(|error| Update::Error { error })
+        }
+        .boxed()
+    }
+}
+
+impl Channel<'_> {
+    fn is_channel(repo: &Repo, _ref: Option<&str>) -> bool {
+        repo.owner.eq_ignore_ascii_case("nixos")
+            && repo.repo == "nixpkgs"
+            && _ref.is_some_and(|r| r.starts_with("nixos-") || r.starts_with("nixpkgs-"))
+    }
+
+    async fn compare(&self, ctx: &Context) -> Result<Update, String> {
+        let url = format!("{}/{}/git-revision", ctx.channels_url, self.channel);
+        let resp = ctx
+            .client
+            .get(&url)
+            .send()
+            .await
+            .map_err(|e| ctx.describe(e))?;
+        if !resp.status().is_success() {
+            return Err(format!("{url} returned {}", resp.status()));
+        }
+        // When the channel last advanced.
+        let updated = resp
+            .headers()
+            .get(LAST_MODIFIED)
+            .and_then(|d| httpdate::parse_http_date(d.to_str().ok()?).ok())
+            .and_then(|d| d.duration_since(UNIX_EPOCH).ok());
+        let latest_rev = resp.text().await.map_err(|e| ctx.describe(e))?;
+        let latest_rev = latest_rev.trim().to_string();
+
+        let outdated = self.repo.rev.as_deref() != Some(latest_rev.as_str());
+        let ahead_by = match outdated {
+            false => Some(0),
+            // Counting commits needs GitHub, the channel is still worth
+            // reporting without it.
+            true => {
+                let github = Github {
+                    use_cli: self.use_gh,
+                    repo: self.repo,
+                    _ref: Some(&latest_rev),
+                };
+                match github.check(ctx).await {
+                    Update::Valid { ahead_by, .. } => Some(ahead_by),
+                    _ => None,
+                }
+            }
+        };
+        let days_behind = match (outdated, updated, self.last_modified) {
+            (false, _, _) => Some(0),
+            (true, Some(updated), Some(locked)) => {
+                Some(updated.as_secs().saturating_sub(locked) / (24 * 60 * 60))
+            }
+            _ => None,
+        };
+        Ok(Update::Channel {
+            channel: self.channel.to_string(),
+            latest_rev,
+            outdated,
+            ahead_by,
+            days_behind,
+        })
+    }
+}
+
+/// Asks any git remote for the latest revision, which tells whether the input
+/// is outdated but not by how much.
+struct GitRemote<'a> {
+    url: String,
+    /// The locked revision.
+    rev: Option<&'a str>,
+    _ref: Option<&'a str>,
+    pinned: Option<&'a str>,
+}
+
+impl Checker for GitRemote<'_> {
+    fn check<'a>(&'a self, ctx: &'a Context) -> BoxFuture<'a, Update> {
+        async move {
+            let latest_rev = match self.pinned {
+                Some(rev) => rev.to_string(),
+                None => match self.ls_remote(ctx).await {
+                    Ok(rev) => rev,
+                    Err(error) => return Update::Error { error },
+                },
+            };
+            Update::Changed {
+                url: self.url.clone(),
+                outdated: self.rev != Some(latest_rev.as_str()),
+                latest_rev,
+            }
+        }
+        .boxed()
+    }
+}
+
+impl GitRemote<'_> {
+    async fn ls_remote(&self, ctx: &Context) -> Result<String, String> {
+        let pattern = self._ref.unwrap_or("HEAD");
+        let out = ctx
+            .output(Command::new("git").args(["ls-remote", &self.url, pattern]))
+            .await
+            .map_err(|e| format!("Couldn't run git: {e}"))?;
+        if !out.status.success() {
+            return Err(String::from_utf8_lossy(&out.stderr).trim().to_string());
+        }
+        let stdout = String::from_utf8_lossy(&out.stdout);
+        match stdout.split_whitespace().next() {
+            Some(rev) => Ok(rev.to_string()),
+            None => Err(format!("{} has no {pattern}", self.url)),
+        }
+    }
+}
+
+/// Inputs that can't be compared with anything, e.g. tarballs or paths.
+struct Unsupported(&'static str);
+
+impl Checker for Unsupported {
+    fn check<'a>(&'a self, _: &'a Context) -> BoxFuture<'a, Update> {
+        let unsupported = format!("{} inputs can't be checked for updates", self.0);
+        async move { Update::Unsupported { unsupported } }.boxed()
+    }
+}
+
+fn read_lockfile(path: &str) -> Result<Lockfile, String> {
+    let contents = fs::read_to_string(path).map_err(|e| format!("Unable to read {path}: {e}"))?;
+    serde_json::from_str::<Lockfile>(&contents).map_err(|e| format!("Unable to parse {path}: {e}"))
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+enum Update {
+    Valid {
+        html_url: String,
+        /// Cached, since the forge is rate limiting.
+        #[serde(default, skip_serializing_if = "std::ops::Not::not")]
+        stale: bool,
+        /// Commits on the followed ref that aren't locked.
+        ahead_by: u64,
+        /// Locked commits that aren't on the followed ref, if known.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        behind_by: Option<u64>,
+        /// When the newest of the missing commits was committed.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        newest_commit_date: Option<String>,
+        /// First lines of the newest missing commits' messages, newest first.
+        #[serde(default, skip_serializing_if = "Vec::is_empty")]
+        commits: Vec<String>,
+    },
+    /// A nixpkgs input compared with the revision its channel is at.
+    Channel {
+        channel: String,
+        latest_rev: String,
+        outdated: bool,
+        /// Commits the channel is ahead by, if GitHub could tell.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        ahead_by: Option<u64>,
+        /// Days between the locked revision and the channel's latest update.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        days_behind: Option<u64>,
+    },
+    /// Only whether there's a newer revision is known.
+    Changed {
+        url: String,
+        latest_rev: String,
+        outdated: bool,
+    },
+    Unsupported {
+        unsupported: String,
+    },
+    Error {
+        error: String,
+    },
+}
+
+impl Update {
+    /// Marks a cached update as possibly out of date.
+    fn into_stale(self) -> Update {
+        match self {
+            Update::Valid {
+                html_url,
+                ahead_by,
+                behind_by,
+                newest_commit_date,
+                commits,
+                ..
+            } => Update::Valid {
+                html_url,
+                stale: true,
+                ahead_by,
+                behind_by,
+                newest_commit_date,
+                commits,
+            },
+            update => update,
+        }
+    }
+}
+
+#[derive(Debug, Serialize, Deserialize, PartialEq)]
+struct Lockfile {
+    nodes: HashMap<String, NodeType>,
+    root: String,
+    version: u16,
+}
+
+/// How long a `follows` chain may be, they can form cycles in broken lock files.
+const MAX_FOLLOWS: usize = 32;
+
+impl Lockfile {
+    fn inputs(&self, node: &str) -> Option<&HashMap<String, InputType>> {
+        match self.nodes.get(node)? {
+            NodeType::Root { inputs } => Some(inputs),
+            NodeType::Node { inputs, .. } => inputs.as_ref(),
+        }
+    }
+
+    /// The node an input refers to.
+    fn resolve<'a>(&'a self, input: &'a InputType, depth: usize) -> Option<&'a str> {
+        match input {
+            InputType::Single(node) => Some(node),
+            // `follows` paths start at the root.
+            InputType::Multiple(path) => {
+                if depth == MAX_FOLLOWS {
+                    return None;
+                }
+                let mut node = self.root.as_str();
+                for name in path {
+                    node = self.resolve(self.inputs(node)?.get(name)?, depth + 1)?;
+                }
+                // An empty path removes the input.
+                (!path.is_empty()).then_some(node)
+            }
+        }
+    }
+
+    /// Every node the root depends on, directly or also transitively, once
+    /// each and with how it's reached.
+    fn walk(&self, transitive: bool) -> BTreeMap<&str, Reached> {
+        let mut nodes = BTreeMap::<&str, Reached>::new();
+        // Nodes to look at with the path of input names leading to them.
+        let mut queue = VecDeque::from([(self.root.as_str(), vec![])]);
+        while let Some((parent, path)) = queue.pop_front() {
+            let Some(inputs) = self.inputs(parent) else {
+                continue;
+            };
+            let mut inputs: Vec<_> = inputs.iter().collect();
+            inputs.sort_by_key(|&(name, _)| name);
+            for (name, input) in inputs {
+                let Some(node) = self.resolve(input, 0) else {
+                    continue;
+                };
+                let mut input_path = path.clone();
+                input_path.push(name.clone());
+                let seen = nodes.contains_key(node);
+                // Breadth first, so the first path is the shortest.
+                let reached = nodes.entry(node).or_insert_with(|| Reached {
+                    name: input_path.join("/"),
+                    via: vec![],
+                });
+                if !path.is_empty() {
+                    reached.via.push(path.join("/"));
+                }
+                if transitive && !seen {
+                    queue.push_back((node, input_path));
+                }
+            }
+        }
+        nodes
+    }
+}
+
+/// How a node is reached from the root.
+#[derive(Debug, PartialEq)]
+struct Reached {
+    /// The path of input names to it, e.g. `hyprland/aquamarine`.
+    name: String,
+    /// Paths of the inputs depending on it.
+    via: Vec<String>,
+}
+
+// Lock files only have a handful of nodes, boxing them isn't worth it.
+#[allow(clippy::large_enum_variant)]
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+enum NodeType {
+    Node {
+        inputs: Option<HashMap<String, InputType>>,
+        original: FlakeRef,
+        locked: Locked,
+    },
+    /// Nodes without a source, only the root.
+    Root {
+        #[serde(default)]
+        inputs: HashMap<String, InputType>,
+    },
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(untagged)]
+enum InputType {
+    Single(String),
+    Multiple(Vec<String>),
+}
+
+#[derive(Debug, Deserialize, Serialize, PartialEq)]
+#[serde(rename_all = "camelCase")]
+struct Locked {
+    #[serde(skip_serializing_if = "Option::is_none")]
+    last_modified: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    nar_hash: Option<String>,
+    #[serde(flatten)]
+    source: FlakeRef,
+}
+
+/// A flake reference, see the "Types" section of `nix flake --help`.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+#[serde(tag = "type", rename_all = "lowercase")]
+enum FlakeRef {
+    GitHub(Repo),
+    GitLab(Repo),
+    SourceHut(Repo),
+    Git {
+        url: String,
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        _ref: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+    },
+    #[serde(rename = "hg")]
+    Mercurial {
+        url: String,
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        _ref: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+    },
+    Tarball {
+        url: String,
+    },
+    File {
+        url: String,
+    },
+    Path {
+        path: String,
+    },
+    /// Looked up in the flake registry, only found in `original`.
+    Indirect {
+        id: String,
+        #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+        _ref: Option<String>,
+        #[serde(skip_serializing_if = "Option::is_none")]
+        rev: Option<String>,
+    },
+    /// A type added after this was written.
+    #[serde(other)]
+    Unknown,
+}
+
+/// A repository on GitHub, GitLab or sourcehut.
+#[derive(Debug, Clone, Deserialize, Serialize, PartialEq)]
+struct Repo {
+    /// Self-hosted instances, e.g. gitlab.freedesktop.org.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    host: Option<String>,
+    owner: String,
+    repo: String,
+    #[serde(rename = "ref", skip_serializing_if = "Option::is_none")]
+    _ref: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    rev: Option<String>,
+}
+
+impl FlakeRef {
+    /// The reference's type, as in the lock file.
+    fn kind(&self) -> &'static str {
+        match self {
+            FlakeRef::GitHub(_) => "github",
+            FlakeRef::GitLab(_) => "gitlab",
+            FlakeRef::SourceHut(_) => "sourcehut",
+            FlakeRef::Git { .. } => "git",
+            FlakeRef::Mercurial { .. } => "hg",
+            FlakeRef::Tarball { .. } => "tarball",
+            FlakeRef::File { .. } => "file",
+            FlakeRef::Path { .. } => "path",
+            FlakeRef::Indirect { .. } => "indirect",
+            FlakeRef::Unknown => "unknown",
+        }
+    }
+
+    /// A short name for the reference, e.g. `nixos/nixpkgs` or its url.
+    fn name(&self) -> String {
+        match self {
+            FlakeRef::GitHub(repo) | FlakeRef::GitLab(repo) | FlakeRef::SourceHut(repo) => {
+                format!("{}/{}", repo.owner, repo.repo)
+            }
+            FlakeRef::Git { url, .. }
+            | FlakeRef::Mercurial { url, .. }
+            | FlakeRef::Tarball { url }
+            | FlakeRef::File { url } => url.clone(),
+            FlakeRef::Path { path } => path.clone(),
+            FlakeRef::Indirect { id, .. } => id.clone(),
+            FlakeRef::Unknown => "unknown".to_string(),
+        }
+    }
+
+    /// The branch or tag followed, and the revision if it's pinned to one.
+    fn followed(&self) -> (Option<&str>, Option<&str>) {
+        match self {
+            FlakeRef::GitHub(repo) | FlakeRef::GitLab(repo) | FlakeRef::SourceHut(repo) => {
+                (repo._ref.as_deref(), repo.rev.as_deref())
+            }
+            FlakeRef::Git { _ref, rev, .. }
+            | FlakeRef::Mercurial { _ref, rev, .. }
+            | FlakeRef::Indirect { _ref, rev, .. } => (_ref.as_deref(), rev.as_deref()),
+            _ => (None, None),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{BufRead, BufReader, Read};
+    use std::net::TcpListener;
+    use std::path::PathBuf;
+    use std::thread::{self, JoinHandle};
+
+    use super::*;
+
+    #[test]
+    fn github_url_formats_correctly_ref_some() {
+        let orig = &FlakeRef::GitHub(Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: Some("some_ref".to_string()),
+            rev: None,
+        });
+        let lock = &Locked {
+            last_modified: Some(12345),
+            nar_hash: Some("some_hash".to_string()),
+            source: FlakeRef::GitHub(Repo {
+                host: None,
+                owner: "nixos".to_string(),
+                repo: "nixpkgs".to_string(),
+                _ref: None,
+                rev: Some("some_rev".to_string()),
+            }),
+        };
+        let FlakeRef::GitHub(repo) = &lock.source else {
+            unreachable!()
+        };
+        let gh = Github {
+            use_cli: false,
+            repo,
+            _ref: orig.followed().0,
+        };
+        let expected = "repos/nixos/nixpkgs/compare/some_rev...some_ref".to_string();
+        assert_eq!(gh.generate_enpoint(), expected);
+    }
+
+    #[test]
+    fn github_url_formats_correctly_ref_none() {
+        let orig = &FlakeRef::GitHub(Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: None,
+        });
+        let lock = &Locked {
+            last_modified: Some(12345),
+            nar_hash: Some("some_hash".to_string()),
+            source: FlakeRef::GitHub(Repo {
+                host: None,
+                owner: "nixos".to_string(),
+                repo: "nixpkgs".to_string(),
+                _ref: None,
+                rev: Some("some_rev".to_string()),
+            }),
+        };
+        let FlakeRef::GitHub(repo) = &lock.source else {
+            unreachable!()
+        };
+        let gh = Github {
+            use_cli: false,
+            repo,
+            _ref: orig.followed().0,
+        };
+        let expected = "repos/nixos/nixpkgs/compare/some_rev...HEAD".to_string();
+        assert_eq!(gh.generate_enpoint(), expected);
+    }
+    #[test]
+    fn root_inputs_are_parsed_correctly() {
+        let expected = HashMap::from([
+            (
+                "home-manager".to_string(),
+                InputType::Single("home-manager".to_string()),
+            ),
+            (
+                "hyprland".to_string(),
+                InputType::Single("hyprland".to_string()),
+            ),
+            (
+                "nixpkgs".to_string(),
+                InputType::Single("nixpkgs".to_string()),
+            ),
+        ]);
+        let lf: Lockfile = serde_json::from_str(
+            r#"
+            {
+                "nodes": {
+                    "root": {
+                        "inputs": {
+                            "home-manager": "home-manager",
+                            "hyprland": "hyprland",
+                            "nixpkgs": "nixpkgs"
+                        }
+                    }
+                },
+                "root": "root",
+                "version": 7
+            }"#,
+        )
+        .expect("Failed to parse Root node into HashMap.");
+        let actual = match lf.nodes.get("root").unwrap() {
+            NodeType::Root { inputs } => inputs,
+            _ => panic!("Should only parse to a root node."),
+        };
+        assert_eq!(&expected, actual);
+    }
+
+    #[test]
+    fn input_nodes_are_parsed_correctly() {
+        let lf: Lockfile = serde_json::from_str(
+            r#"
+            {
+              "nodes": {
+                "nixpkgs": {
+                  "locked": {
+                    "lastModified": 1690640159,
+                    "narHash": "sha256-5DZUYnkeMOsVb/eqPYb9zns5YsnQXRJRC8Xx/nPMcno=",
+                    "owner": "nixos",
+                    "repo": "nixpkgs",
+                    "rev": "e6ab46982debeab9831236869539a507f670a129",
+                    "type": "github"
+                  },
+                  "original": {
+                    "owner": "nixos",
+                    "ref": "nixos-unstable",
+                    "repo": "nixpkgs",
+                    "type": "github"
+                  }
+                }
+              },
+              "root": "root",
+              "version": 7
+            }
+        "#,
+        )
+        .expect("Failed to parse lockfile.");
+        let (actual_locked, actual_original) = match lf.nodes.get("nixpkgs").unwrap() {
+            NodeType::Node {
+                locked, original, ..
+            } => (locked, original),
+            _ => panic!("Should only parse to an input node."),
+        };
+        let expected_locked = Locked {
+            last_modified: Some(1690640159),
+            nar_hash: Some("sha256-5DZUYnkeMOsVb/eqPYb9zns5YsnQXRJRC8Xx/nPMcno=".to_string()),
+            source: FlakeRef::GitHub(Repo {
+                host: None,
+                owner: "nixos".to_string(),
+                repo: "nixpkgs".to_string(),
+                _ref: None,
+                rev: Some("e6ab46982debeab9831236869539a507f670a129".to_string()),
+            }),
+        };
+        let expected_original = FlakeRef::GitHub(Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: Some("nixos-unstable".to_string()),
+            rev: None,
+        });
+        assert_eq!(&expected_locked, actual_locked);
+        assert_eq!(&expected_original, actual_original);
+    }
+
+    #[test]
+    fn locked_is_parsed_correctly() {
+        let actual: Locked = serde_json::from_str(
+            r#"
+            {
+                "lastModified": 1690640159,
+                "narHash": "sha256-5DZUYnkeMOsVb/eqPYb9zns5YsnQXRJRC8Xx/nPMcno=",
+                "owner": "nixos",
+                "repo": "nixpkgs",
+                "rev": "e6ab46982debeab9831236869539a507f670a129",
+                "type": "github"
+             }
+        "#,
+        )
+        .unwrap();
+        let expected = Locked {
+            last_modified: Some(1690640159),
+            nar_hash: Some("sha256-5DZUYnkeMOsVb/eqPYb9zns5YsnQXRJRC8Xx/nPMcno=".to_string()),
+            source: FlakeRef::GitHub(Repo {
+                host: None,
+                owner: "nixos".to_string(),
+                repo: "nixpkgs".to_string(),
+                _ref: None,
+                rev: Some("e6ab46982debeab9831236869539a507f670a129".to_string()),
+            }),
+        };
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    /// '.nodes.xdph` wasn't parsing, not sure why
+    fn ensure_xdph_is_parsed_correctly() {
+        let actual: Lockfile = serde_json::from_str(
+            r#"
+            {
+              "root": "root",
+              "version": 7,
+              "nodes": {
+                "xdph": {
+                  "inputs": {
+                    "hyprland-protocols": [
+                      "hyprland",
+                      "hyprland-protocols"
+                    ],
+                    "nixpkgs": [
+                      "hyprland",
+                      "nixpkgs"
+                    ]
+                  },
+                  "locked": {
+                    "lastModified": 1685385764,
+                    "narHash": "sha256-r+XMyOoRXq+hlfjayb+fyi9kq2JK48TrwuNIAXqlj7U=",
+                    "owner": "hyprwm",
+                    "repo": "xdg-desktop-portal-hyprland",
+                    "rev": "4d9ff0c17716936e0b5ca577a39e263633901ed1",
+                    "type": "github"
+                  },
+                  "original": {
+                    "owner": "hyprwm",
+                    "repo": "xdg-desktop-portal-hyprland",
+                    "type": "github"
+                  }
+                }
+              }
+            }
+        "#,
+        )
+        .expect("Failed to parse json");
+
+        let inputs = HashMap::from([
+            (
+                "hyprland-protocols".to_string(),
+                InputType::Multiple(vec![
+                    "hyprland".to_string(),
+                    "hyprland-protocols".to_string(),
+                ]),
+            ),
+            (
+                "nixpkgs".to_string(),
+                InputType::Multiple(vec!["hyprland".to_string(), "nixpkgs".to_string()]),
+            ),
+        ]);
+        let node = NodeType::Node {
+            inputs: Some(inputs),
+            original: FlakeRef::GitHub(Repo {
+                host: None,
+                owner: "hyprwm".to_string(),
+                repo: "xdg-desktop-portal-hyprland".to_string(),
+                _ref: None,
+                rev: None,
+            }),
+            locked: Locked {
+                last_modified: Some(1685385764),
+                nar_hash: Some("sha256-r+XMyOoRXq+hlfjayb+fyi9kq2JK48TrwuNIAXqlj7U=".to_string()),
+                source: FlakeRef::GitHub(Repo {
+                    host: None,
+                    owner: "hyprwm".to_string(),
+                    repo: "xdg-desktop-portal-hyprland".to_string(),
+                    _ref: None,
+                    rev: Some("4d9ff0c17716936e0b5ca577a39e263633901ed1".to_string()),
+                }),
+            },
+        };
+        let expected = Lockfile {
+            nodes: HashMap::from([("xdph".to_string(), node)]),
+            root: "root".to_string(),
+            version: 7,
+        };
+        assert_eq!(expected, actual);
+    }
+
+    #[test]
+    fn node_that_is_not_flake() {
+        let actual: Lockfile = serde_json::from_str(
+            r#"
+            {
+              "root": "root",
+              "version": 7,
+              "nodes": {
+                "wlroots": {
+                  "flake": false,
+                  "locked": {
+                    "host": "gitlab.freedesktop.org",
+                    "lastModified": 1690165843,
+                    "narHash": "sha256-gv5kjss6REeQG0BmvK2gTx7jHLRdCnP25po6It6I6N8=",
+                    "owner": "wlroots",
+                    "repo": "wlroots",
+                    "rev": "e8d545a9770a2473db32e0a0bfa757b05d2af4f3",
+                    "type": "gitlab"
+                  },
+                  "original": {
+                    "host": "gitlab.freedesktop.org",
+                    "owner": "wlroots",
+                    "repo": "wlroots",
+                    "rev": "e8d545a9770a2473db32e0a0bfa757b05d2af4f3",
+                    "type": "gitlab"
+                  }
+                }
+              }
+            }
+        "#,
+        )
+        .expect("Failed to parse json");
+        let expected = Lockfile {
+            nodes: HashMap::from([(
+                "wlroots".to_string(),
+                NodeType::Node {
+                    inputs: None,
+                    original: FlakeRef::GitLab(Repo {
+                        host: Some("gitlab.freedesktop.org".to_string()),
+                        owner: "wlroots".to_string(),
+                        repo: "wlroots".to_string(),
+                        _ref: None,
+                        rev: Some("e8d545a9770a2473db32e0a0bfa757b05d2af4f3".to_string()),
+                    }),
+                    locked: Locked {
+                        last_modified: Some(1690165843),
+                        nar_hash: Some(
+                            "sha256-gv5kjss6REeQG0BmvK2gTx7jHLRdCnP25po6It6I6N8=".to_string(),
+                        ),
+                        source: FlakeRef::GitLab(Repo {
+                            host: Some("gitlab.freedesktop.org".to_string()),
+                            owner: "wlroots".to_string(),
+                            repo: "wlroots".to_string(),
+                            _ref: None,
+                            rev: Some("e8d545a9770a2473db32e0a0bfa757b05d2af4f3".to_string()),
+                        }),
+                    },
+                },
+            )]),
+            root: "root".to_string(),
+            version: 7,
+        };
+        assert_eq!(expected, actual)
+    }
+
+    /// A directory of the test's own, so parallel tests don't share a cache.
+    fn test_dir(test: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("nasty-{test}-{}", std::process::id()))
+    }
+
+    fn block_on<F: std::future::Future>(future: F) -> F::Output {
+        tokio::runtime::Builder::new_current_thread()
+            .enable_all()
+            .build()
+            .unwrap()
+            .block_on(future)
+    }
+
+    /// Runs a check without the GitHub CLI.
+    fn check(test: &str, checker: &dyn Checker) -> Update {
+        check_within(test, checker, TIMEOUT)
+    }
+
+    fn check_within(test: &str, checker: &dyn Checker, timeout: Duration) -> Update {
+        block_on(checker.check(&context(test, timeout)))
+    }
+
+    /// A context without the GitHub CLI, caching in the test's directory.
+    fn context(test: &str, timeout: Duration) -> Context {
+        Context {
+            client: reqwest::Client::builder().timeout(timeout).build().unwrap(),
+            use_gh: false,
+            timeout,
+            rate_limit_reset: Mutex::new(None),
+            cache: Cache::new(test_dir(test), Duration::ZERO),
+            github_api: "https://api.github.com".to_string(),
+            github_token: None,
+            github_rate_limit: Mutex::new(None),
+            channels_url: "https://channels.nixos.org".to_string(),
+            sourcehut_token: None,
+        }
+    }
+
+    /// A response closing its connection, with `headers` ending in `\r\n`.
+    fn response(status: &str, headers: &str, body: &str) -> String {
+        format!(
+            "HTTP/1.1 {status}\r\n{headers}Content-Length: {}\r\n\
+            Connection: close\r\n\r\n{body}",
+            body.len()
+        )
+    }
+
+    /// Answers each request with the next response, and returns the requests,
+    /// bodies included.
+    fn serve(responses: Vec<String>) -> (String, JoinHandle<Vec<String>>) {
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let url = format!("http://{}", listener.local_addr().unwrap());
+        let server = thread::spawn(move || {
+            let mut requests = vec![];
+            for response in responses {
+                let (mut stream, _) = listener.accept().unwrap();
+                let mut request = String::new();
+                let mut reader = BufReader::new(stream.try_clone().unwrap());
+                while reader.read_line(&mut request).unwrap() > 2 {}
+                let length = request.lines().find_map(|l| {
+                    l.to_lowercase()
+                        .strip_prefix("content-length: ")?
+                        .parse()
+                        .ok()
+                });
+                let mut body = vec![0; length.unwrap_or(0)];
+                reader.read_exact(&mut body).unwrap();
+                request.push_str(&String::from_utf8_lossy(&body));
+                stream.write_all(response.as_bytes()).unwrap();
+                requests.push(request);
+            }
+            requests
+        });
+        (url, server)
+    }
+
+    #[test]
+    fn github_tokens_are_picked_in_order() {
+        let file = std::env::temp_dir().join(format!("nasty-token-{}", std::process::id()));
+        fs::write(&file, "from-file\n").unwrap();
+        let settings = config::Updates {
+            github_token: Some("from-config".to_string()),
+            github_token_file: Some(file.to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let env = |vars: &'static [(&str, &str)]| {
+            move |var: &str| {
+                vars.iter()
+                    .find(|(name, _)| *name == var)
+                    .map(|(_, value)| value.to_string())
+            }
+        };
+        let both = env(&[("GITHUB_TOKEN", "github"), ("GH_TOKEN", "gh")]);
+        assert_eq!(github_token(both, &settings).as_deref(), Some("github"));
+        let gh = env(&[("GITHUB_TOKEN", ""), ("GH_TOKEN", "gh")]);
+        assert_eq!(github_token(gh, &settings).as_deref(), Some("gh"));
+        assert_eq!(
+            github_token(env(&[]), &settings).as_deref(),
+            Some("from-config")
+        );
+        let file_only = config::Updates {
+            github_token: None,
+            ..settings.clone()
+        };
+        assert_eq!(
+            github_token(env(&[]), &file_only).as_deref(),
+            Some("from-file")
+        );
+        fs::remove_file(&file).unwrap();
+        assert_eq!(github_token(env(&[]), &file_only), None);
+        assert_eq!(github_token(env(&[]), &Default::default()), None);
+    }
+
+    #[test]
+    fn github_requests_use_the_token() {
+        let body = r#"{"html_url":"https://github.com","ahead_by":0,"behind_by":0,"commits":[]}"#;
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            "x-ratelimit-limit: 5000\r\nx-ratelimit-remaining: 4999\r\n\
+            x-ratelimit-reset: 1720000000\r\n",
+            body,
+        )]);
+        let ctx = Context {
+            github_api: url,
+            github_token: Some("secret".to_string()),
+            ..context("token-cache", TIMEOUT)
+        };
+        let repo = Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: Some("abc".to_string()),
+        };
+        let gh = Github {
+            use_cli: false,
+            repo: &repo,
+            _ref: None,
+        };
+        let Update::Valid { .. } = block_on(gh.check(&ctx)) else {
+            panic!("The response is valid");
+        };
+        let request = &server.join().unwrap()[0];
+        assert!(
+            request.contains("authorization: Bearer secret"),
+            "{request}"
+        );
+        assert_eq!(
+            *ctx.github_rate_limit.lock().unwrap(),
+            Some(RateLimit {
+                limit: 5000,
+                remaining: 4999,
+                reset: 1720000000
+            })
+        );
+        fs::remove_dir_all(test_dir("token-cache")).unwrap();
+    }
+
+    #[test]
+    fn nixpkgs_inputs_are_compared_with_their_channel() {
+        let revision = |rev: &str| {
+            response(
+                "200 OK",
+                "Last-Modified: Sun, 07 Jul 2024 00:00:00 GMT\r\n",
+                &format!("{rev}\n"),
+            )
+        };
+        let compare = r#"{"html_url":"https://github.com/NixOS/nixpkgs/compare/abc...def",
+            "ahead_by":1200,"behind_by":0,"commits":[]}"#;
+        let (url, server) = serve(vec![
+            revision("def"),
+            response("200 OK", "", compare),
+            revision("def"),
+        ]);
+        let ctx = Context {
+            github_api: url.clone(),
+            channels_url: url,
+            ..context("channel-cache", TIMEOUT)
+        };
+        let repo = |rev: &str| Repo {
+            host: None,
+            owner: "NixOS".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: Some(rev.to_string()),
+        };
+        assert!(Channel::is_channel(&repo("abc"), Some("nixos-24.05")));
+        assert!(!Channel::is_channel(&repo("abc"), Some("master")));
+        assert!(!Channel::is_channel(&repo("abc"), None));
+
+        let (behind, latest) = (repo("abc"), repo("def"));
+        let channel = |repo| Channel {
+            use_gh: false,
+            repo,
+            channel: "nixos-unstable",
+            // 2024-07-04
+            last_modified: Some(1720051200),
+        };
+        assert_eq!(
+            block_on(channel(&behind).check(&ctx)),
+            Update::Channel {
+                channel: "nixos-unstable".to_string(),
+                latest_rev: "def".to_string(),
+                outdated: true,
+                ahead_by: Some(1200),
+                days_behind: Some(3),
+            }
+        );
+        assert_eq!(
+            block_on(channel(&latest).check(&ctx)),
+            Update::Channel {
+                channel: "nixos-unstable".to_string(),
+                latest_rev: "def".to_string(),
+                outdated: false,
+                ahead_by: Some(0),
+                days_behind: Some(0),
+            }
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].starts_with("GET /nixos-unstable/git-revision "));
+        assert!(requests[1].starts_with("GET /repos/NixOS/nixpkgs/compare/abc...def "));
+        fs::remove_dir_all(test_dir("channel-cache")).unwrap();
+    }
+
+    #[test]
+    fn github_compares_are_cached() {
+        let compare = r#"{"html_url":"https://github.com/nixos/nixpkgs/compare/abc...HEAD",
+            "ahead_by":3,"behind_by":0,"commits":[]}"#;
+        let (url, server) = serve(vec![
+            response("200 OK", "ETag: \"v1\"\r\n", compare),
+            response("304 Not Modified", "ETag: \"v1\"\r\n", ""),
+            response("403 Forbidden", "x-ratelimit-reset: 1\r\n", "{}"),
+        ]);
+        let mut ctx = Context {
+            github_api: url,
+            ..context("github-cache", TIMEOUT)
+        };
+        let repo = Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: Some("abc".to_string()),
+        };
+        let gh = Github {
+            use_cli: false,
+            repo: &repo,
+            _ref: None,
+        };
+        let fresh = Update::Valid {
+            html_url: "https://github.com/nixos/nixpkgs/compare/abc...HEAD".to_string(),
+            stale: false,
+            ahead_by: 3,
+            behind_by: Some(0),
+            newest_commit_date: None,
+            commits: vec![],
+        };
+        assert_eq!(block_on(gh.check(&ctx)), fresh);
+        // Unchanged, so it's served from the cache.
+        assert_eq!(block_on(gh.check(&ctx)), fresh);
+        assert_eq!(
+            block_on(gh.check(&ctx)),
+            fresh.clone().into_stale(),
+            "Rate limited"
+        );
+        let requests = server.join().unwrap();
+        assert!(!requests[0].contains("if-none-match"), "{}", requests[0]);
+        assert!(
+            requests[1].contains("if-none-match: \"v1\""),
+            "{}",
+            requests[1]
+        );
+
+        // Within the TTL, there's no request at all.
+        ctx.cache = Cache::new(test_dir("github-cache"), Duration::from_secs(60));
+        assert_eq!(block_on(gh.check(&ctx)), fresh);
+        fs::remove_dir_all(test_dir("github-cache")).unwrap();
+    }
+
+    #[test]
+    fn unmodified_compares_without_an_entry_are_asked_for_again() {
+        let compare = r#"{"html_url":"https://github.com/nixos/nixpkgs/compare/abc...HEAD",
+            "ahead_by":3,"behind_by":0,"commits":[]}"#;
+        let (url, server) = serve(vec![
+            response("304 Not Modified", "", ""),
+            response("200 OK", "", compare),
+        ]);
+        let ctx = Context {
+            github_api: url,
+            ..context("github-304", TIMEOUT)
+        };
+        let repo = Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: Some("abc".to_string()),
+        };
+        let gh = Github {
+            use_cli: false,
+            repo: &repo,
+            _ref: None,
+        };
+        let Update::Valid { ahead_by: 3, .. } = block_on(gh.check(&ctx)) else {
+            panic!("The comparison is asked for again");
+        };
+        assert_eq!(server.join().unwrap().len(), 2);
+        fs::remove_dir_all(test_dir("github-304")).unwrap();
+    }
+
+    #[test]
+    fn the_rate_limit_is_reported_once() {
+        let dir = test_dir("quota");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flake.lock");
+        let node = r#"{"locked": {"type": "path", "path": "/src"},
+            "original": {"type": "path", "path": "/src"}}"#;
+        fs::write(
+            &path,
+            format!(
+                r#"{{"nodes": {{"root": {{"inputs": {{"a": "a", "b": "b"}}}},
+                "a": {node}, "b": {node}}}, "root": "root", "version": 7}}"#
+            ),
+        )
+        .unwrap();
+        let ctx = context("quota", TIMEOUT);
+        let quota = RateLimit {
+            limit: 60,
+            remaining: 42,
+            reset: 1720000000,
+        };
+        *ctx.github_rate_limit.lock().unwrap() = Some(quota);
+        let settings = config::Updates {
+            lock_file: path.to_str().unwrap().to_string(),
+            ..Default::default()
+        };
+        let updates: serde_json::Value =
+            serde_json::from_str(&block_on(check_nixos(&settings, &ctx))).unwrap();
+        assert_eq!(
+            updates["rate_limit"],
+            serde_json::json!({"limit": 60, "remaining": 42, "reset": 1720000000})
+        );
+        assert!(updates["inputs"]["a"]["rate_limit"].is_null(), "{updates}");
+        assert!(updates["inputs"]["b"]["rate_limit"].is_null(), "{updates}");
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn checks_wait_for_rate_limits_to_reset() {
+        let ctx = context("reset", TIMEOUT);
+        let interval = Duration::from_secs(60);
+        assert_eq!(ctx.next_check(interval), interval);
+        ctx.rate_limited_until(unix_now() + 60 * 60);
+        assert!(ctx.next_check(interval) > Duration::from_secs(59 * 60));
+        // Until it has reset, not just until the next check.
+        assert!(ctx.next_check(interval) > Duration::from_secs(59 * 60));
+        let reset = context("reset", TIMEOUT);
+        reset.rate_limited_until(unix_now() - 1);
+        assert_eq!(reset.next_check(interval), interval);
+        assert_eq!(reset.rate_limited(), None);
+    }
+
+    #[test]
+    fn rate_limited_checks_make_no_requests() {
+        // Nothing listens there, a request would be an error.
+        let ctx = Context {
+            github_api: "http://127.0.0.1:1".to_string(),
+            ..context("rate-limited", TIMEOUT)
+        };
+        ctx.rate_limited_until(unix_now() + 60 * 60);
+        let repo = Repo {
+            host: None,
+            owner: "nixos".to_string(),
+            repo: "nixpkgs".to_string(),
+            _ref: None,
+            rev: Some("abc".to_string()),
+        };
+        let gh = Github {
+            use_cli: false,
+            repo: &repo,
+            _ref: None,
+        };
+        let Update::Error { error } = block_on(gh.check(&ctx)) else {
+            panic!("Rate limited without a cached result");
+        };
+        assert!(
+            error.starts_with("Rate limited... try again in "),
+            "{error}"
+        );
+
+        let cached = Update::Valid {
+            html_url: "https://github.com".to_string(),
+            stale: false,
+            ahead_by: 3,
+            behind_by: Some(0),
+            newest_commit_date: None,
+            commits: vec![],
+        };
+        let entry = Entry {
+            etag: Some("\"v1\"".to_string()),
+            fetched: 0,
+            update: cached.clone(),
+        };
+        ctx.cache.put(&gh.cache_key(), &entry);
+        assert_eq!(block_on(gh.check(&ctx)), cached.into_stale());
+        fs::remove_dir_all(test_dir("rate-limited")).unwrap();
+    }
+
+    /// Sends each line written to it.
+    struct Lines(mpsc::UnboundedSender<String>, String);
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.1.push_str(&String::from_utf8_lossy(buf));
+            while let Some(i) = self.1.find('\n') {
+                let line: String = self.1.drain(..=i).collect();
+                let _ = self.0.send(line.trim_end().to_string());
+            }
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn written_lock_files_are_rechecked() {
+        let dir = test_dir("watch");
+        fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("flake.lock");
+        // Path inputs are checked without the network.
+        let lock_file = |inputs: &[&str]| {
+            let node = r#"{"locked": {"type": "path", "path": "/src"},
+                "original": {"type": "path", "path": "/src"}}"#;
+            let nodes: Vec<_> = inputs.iter().map(|i| format!(r#""{i}": {node}"#)).collect();
+            let root: Vec<_> = inputs.iter().map(|i| format!(r#""{i}": "{i}""#)).collect();
+            format!(
+                r#"{{"nodes": {{"root": {{"inputs": {{{}}}}}, {}}}, "root": "root", "version": 7}}"#,
+                root.join(","),
+                nodes.join(",")
+            )
+        };
+        fs::write(&path, lock_file(&["a"])).unwrap();
+
+        let rt = tokio::runtime::Runtime::new().unwrap();
+        rt.block_on(async {
+            let (tx, mut rx) = mpsc::unbounded_channel();
+            let config = config::Config {
+                updates: config::Updates {
+                    lock_file: path.to_str().unwrap().to_string(),
+                    interval: 60 * 60,
+                    ..Default::default()
+                },
+                ..Default::default()
+            };
+            let (config_tx, config_rx) = watch::channel(config.clone());
+            tokio::spawn(async move {
+                let mut out = Lines(tx, String::new());
+                watch_nixos(config_rx, &Default::default(), &mut out).await
+            });
+            let timeout = Duration::from_secs(10);
+            let first = tokio::time::timeout(timeout, rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(first.starts_with(r#"{"inputs":{"a":"#), "{first}");
+            fs::write(&path, lock_file(&["a", "b"])).unwrap();
+            let second = tokio::time::timeout(timeout, rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(second.contains(r#""b":"#), "{second}");
+
+            // Another lock file in the config is checked right away.
+            let other = dir.join("other.lock");
+            fs::write(&other, lock_file(&["c"])).unwrap();
+            let mut updated = config.clone();
+            updated.updates.lock_file = other.to_str().unwrap().to_string();
+            config_tx.send(updated).unwrap();
+            let third = tokio::time::timeout(timeout, rx.recv())
+                .await
+                .unwrap()
+                .unwrap();
+            assert!(third.starts_with(r#"{"inputs":{"c":"#), "{third}");
+        });
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// Where wlroots is locked to.
+    fn wlroots() -> Repo {
+        Repo {
+            host: Some("gitlab.freedesktop.org".to_string()),
+            owner: "wlroots".to_string(),
+            repo: "wlroots".to_string(),
+            _ref: None,
+            rev: Some("e8d545a".to_string()),
+        }
+    }
+
+    #[test]
+    fn gitlab_uses_the_inputs_host() {
+        let repo = wlroots();
+        let gl = Gitlab::new(&repo, None, None);
+        assert_eq!(
+            gl.project_endpoint(),
+            "https://gitlab.freedesktop.org/api/v4/projects/wlroots%2Fwlroots"
+        );
+    }
+
+    #[test]
+    fn gitlab_compares_with_the_default_branch() {
+        let (url, server) = serve(vec![
+            response("200 OK", "", r#"{"id":42,"default_branch":"master"}"#),
+            response(
+                "200 OK",
+                "",
+                r#"{"commits":[
+                  {"id":"a","title":"Fix a leak","committed_date":"2024-07-01T10:00:00.000+00:00"},
+                  {"id":"b","title":"Add a protocol","committed_date":"2024-07-02T10:00:00.000+00:00"}
+                ],"diffs":[],
+                "web_url":"https://gitlab.freedesktop.org/wlroots/wlroots/-/compare/e8d545a...master"}"#,
+            ),
+        ]);
+        let gl = Gitlab {
+            api_url: format!("{url}/api/v4"),
+            repo: &wlroots(),
+            _ref: None,
+            pinned: None,
+        };
+        assert_eq!(
+            check("gitlab-default-branch", &gl),
+            Update::Valid {
+                html_url:
+                    "https://gitlab.freedesktop.org/wlroots/wlroots/-/compare/e8d545a...master"
+                        .to_string(),
+                stale: false,
+                ahead_by: 2,
+                behind_by: None,
+                newest_commit_date: Some("2024-07-02T10:00:00.000+00:00".to_string()),
+                commits: vec!["Add a protocol".to_string(), "Fix a leak".to_string()],
+            }
+        );
+        let requests = server.join().unwrap();
+        assert!(
+            requests[0].starts_with("GET /api/v4/projects/wlroots%2Fwlroots "),
+            "{}",
+            requests[0]
+        );
+        assert!(
+            requests[1].starts_with(
+                "GET /api/v4/projects/wlroots%2Fwlroots/repository/compare?from=e8d545a&to=master "
+            ),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[test]
+    fn sourcehut_walks_the_log_to_the_locked_revision() {
+        let (url, server) = serve(vec![
+            response(
+                "200 OK",
+                "",
+                r#"{"data":{"user":{"repository":{"log":{"results":[
+                  {"id":"c3","title":"Release 1.5","committer":{"time":"2024-07-03T10:00:00Z"}},
+                  {"id":"c2","title":"Fix outputs","committer":{"time":"2024-07-02T10:00:00Z"}}
+                ],"cursor":"next"}}}}}"#,
+            ),
+            response(
+                "200 OK",
+                "",
+                r#"{"data":{"user":{"repository":{"log":{"results":[
+                  {"id":"c1","title":"Add a flag","committer":{"time":"2024-07-01T10:00:00Z"}},
+                  {"id":"c0","title":"Locked","committer":{"time":"2024-06-01T10:00:00Z"}}
+                ],"cursor":"last"}}}}}"#,
+            ),
+        ]);
+        let repo = Repo {
+            host: None,
+            owner: "~emersion".to_string(),
+            repo: "kanshi".to_string(),
+            _ref: Some("master".to_string()),
+            rev: Some("c0".to_string()),
+        };
+        let srht = Sourcehut {
+            api_url: format!("{url}/query"),
+            ..Sourcehut::new(&repo, Some("master"), None)
+        };
+        let ctx = Context {
+            sourcehut_token: Some("secret".to_string()),
+            ..context("sourcehut-log", TIMEOUT)
+        };
+        assert_eq!(
+            block_on(srht.check(&ctx)),
+            Update::Valid {
+                html_url: "https://git.sr.ht/~emersion/kanshi/log/master".to_string(),
+                stale: false,
+                ahead_by: 3,
+                behind_by: None,
+                newest_commit_date: Some("2024-07-03T10:00:00Z".to_string()),
+                commits: vec![
+                    "Release 1.5".to_string(),
+                    "Fix outputs".to_string(),
+                    "Add a flag".to_string()
+                ],
+            }
+        );
+        let requests = server.join().unwrap();
+        assert!(requests[0].contains("authorization: Bearer secret"));
+        assert!(
+            requests[0].contains(r#""owner":"emersion""#),
+            "{}",
+            requests[0]
+        );
+        assert!(
+            requests[1].contains(r#""cursor":"next""#),
+            "{}",
+            requests[1]
+        );
+    }
+
+    #[test]
+    fn gitlab_refs_are_encoded() {
+        let (url, server) = serve(vec![response(
+            "200 OK",
+            "",
+            r#"{"commits":[],"web_url":"https://gitlab.freedesktop.org"}"#,
+        )]);
+        let gl = Gitlab {
+            api_url: format!("{url}/api/v4"),
+            repo: &wlroots(),
+            _ref: Some("release/0.18 + fixes"),
+            pinned: None,
+        };
+        let Update::Valid { ahead_by: 0, .. } = check("gitlab-refs", &gl) else {
+            panic!("The ref is compared with");
+        };
+        let requests = server.join().unwrap();
+        assert!(
+            requests[0].starts_with(
+                "GET /api/v4/projects/wlroots%2Fwlroots/repository/compare\
+                ?from=e8d545a&to=release%2F0.18+%2B+fixes "
+            ),
+            "{}",
+            requests[0]
+        );
+
+        // Without a locked revision there's nothing to compare.
+        let unlocked = Repo {
+            rev: None,
+            ..wlroots()
+        };
+        let gl = Gitlab::new(&unlocked, Some("master"), None);
+        assert_eq!(
+            check("gitlab-refs", &gl),
+            Update::Error {
+                error: "The input isn't locked to a revision".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn slow_forges_time_out() {
+        // Connections are accepted by the OS, but never answered.
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let gl = Gitlab {
+            api_url: format!("http://{}/api/v4", listener.local_addr().unwrap()),
+            repo: &wlroots(),
+            _ref: Some("master"),
+            pinned: None,
+        };
+        assert_eq!(
+            check_within("slow-forges", &gl, Duration::from_millis(200)),
+            Update::Error {
+                error: "Timed out after 0.2s".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn gitlab_errors_are_reported() {
+        let (url, server) = serve(vec![response(
+            "404 Not Found",
+            "",
+            r#"{"message":"404 Project Not Found"}"#,
+        )]);
+        let gl = Gitlab {
+            api_url: format!("{url}/api/v4"),
+            repo: &wlroots(),
+            _ref: Some("0.16"),
+            pinned: None,
+        };
+        let Update::Error { error } = check("gitlab-errors", &gl) else {
+            panic!("The project doesn't exist");
+        };
+        assert!(error.ends_with("returned 404 Not Found"), "{error}");
+        let requests = server.join().unwrap();
+        assert!(
+            requests[0].starts_with(
+                "GET /api/v4/projects/wlroots%2Fwlroots/repository/compare?from=e8d545a&to=0.16 "
+            ),
+            "{}",
+            requests[0]
+        );
+    }
+
+    #[test]
+    fn every_input_type_is_parsed() {
+        let nodes: HashMap<String, FlakeRef> = serde_json::from_str(
+            r#"
+            {
+              "git": {"type": "git", "url": "https://codeberg.org/river/river", "ref": "main"},
+              "sourcehut": {"type": "sourcehut", "owner": "~emersion", "repo": "kanshi"},
+              "hg": {"type": "hg", "url": "https://hg.example.org/repo"},
+              "tarball": {"type": "tarball", "url": "https://example.org/src.tar.gz"},
+              "file": {"type": "file", "url": "https://example.org/default.nix"},
+              "path": {"type": "path", "path": "/home/kgb33/nasty"},
+              "indirect": {"type": "indirect", "id": "nixpkgs", "ref": "nixos-24.05"},
+              "new": {"type": "fossil", "url": "https://example.org"}
+            }
+        "#,
+        )
+        .expect("Failed to parse json");
+        assert_eq!(
+            nodes["git"],
+            FlakeRef::Git {
+                url: "https://codeberg.org/river/river".to_string(),
+                _ref: Some("main".to_string()),
+                rev: None,
+            }
+        );
+        assert_eq!(nodes["sourcehut"].name(), "~emersion/kanshi");
+        assert_eq!(nodes["path"].kind(), "path");
+        assert_eq!(nodes["indirect"].followed(), (Some("nixos-24.05"), None));
+        assert_eq!(nodes["new"], FlakeRef::Unknown);
+
+        let locked: Locked = serde_json::from_str(
+            r#"{"lastModified": 1, "narHash": "sha256-abc", "path": "/nix/store/abc", "type": "path"}"#,
+        )
+        .unwrap();
+        assert_eq!(
+            check(
+                "path",
+                checker_factory(&locked, &nodes["path"], false).as_ref()
+            ),
+            Update::Unsupported {
+                unsupported: "path inputs can't be checked for updates".to_string()
+            }
+        );
+    }
+
+    #[test]
+    fn git_remotes_report_newer_revisions() {
+        let dir = test_dir("git");
+        let git = |args: &[&str]| {
+            let out = std::process::Command::new("git")
+                .args(["-c", "user.name=nasty", "-c", "user.email=nasty@localhost"])
+                .arg("-C")
+                .arg(&dir)
+                .args(args)
+                .output()
+                .unwrap();
+            assert!(out.status.success(), "{out:?}");
+            String::from_utf8(out.stdout).unwrap().trim().to_string()
+        };
+        fs::create_dir_all(&dir).unwrap();
+        git(&["init", "--quiet", "--initial-branch=main"]);
+        git(&["commit", "--quiet", "--allow-empty", "-m", "first"]);
+        let first = git(&["rev-parse", "HEAD"]);
+        git(&["commit", "--quiet", "--allow-empty", "-m", "second"]);
+        let second = git(&["rev-parse", "HEAD"]);
+
+        let url = dir.to_str().unwrap().to_string();
+        let remote = |rev, _ref| GitRemote {
+            url: url.clone(),
+            rev,
+            _ref,
+            pinned: None,
+        };
+        assert_eq!(
+            check("git", &remote(Some(&first), Some("main"))),
+            Update::Changed {
+                url: url.clone(),
+                latest_rev: second.clone(),
+                outdated: true,
+            }
+        );
+        assert_eq!(
+            check("git", &remote(Some(&second), None)),
+            Update::Changed {
+                url: url.clone(),
+                latest_rev: second.clone(),
+                outdated: false,
+            }
+        );
+        let Update::Error { .. } = check("git", &remote(Some(&second), Some("nope"))) else {
+            panic!("The branch doesn't exist");
+        };
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A node locked to `nixos/nixpkgs` with `inputs`.
+    fn node(inputs: &str) -> String {
+        format!(
+            r#"{{"inputs": {inputs},
+            "locked": {{"owner": "nixos", "repo": "nixpkgs", "rev": "abc", "type": "github"}},
+            "original": {{"owner": "nixos", "repo": "nixpkgs", "type": "github"}}}}"#
+        )
+    }
+
+    #[test]
+    fn the_input_graph_is_walked() {
+        let lf: Lockfile = serde_json::from_str(&format!(
+            r#"{{
+              "nodes": {{
+                "root": {{"inputs": {{"hyprland": "hyprland", "nixpkgs": "nixpkgs"}}}},
+                "hyprland": {},
+                "aquamarine": {},
+                "nixpkgs": {},
+                "nixpkgs_2": {},
+                "systems": {}
+              }},
+              "root": "root",
+              "version": 7
+            }}"#,
+            node(
+                r#"{"aquamarine": "aquamarine", "nixpkgs": ["nixpkgs"],
+                "systems": ["hyprland", "aquamarine", "systems"], "removed": []}"#
+            ),
+            node(r#"{"nixpkgs": "nixpkgs_2", "systems": "systems"}"#),
+            node("{}"),
+            node("{}"),
+            node(r#"{"cycle": ["hyprland", "systems", "cycle"]}"#),
+        ))
+        .unwrap();
+
+        let reached = |name: &str, via: &[&str]| Reached {
+            name: name.to_string(),
+            via: via.iter().map(|v| v.to_string()).collect(),
+        };
+        let direct = BTreeMap::from([
+            ("hyprland", reached("hyprland", &[])),
+            ("nixpkgs", reached("nixpkgs", &[])),
+        ]);
+        assert_eq!(lf.walk(false), direct);
+        let all = BTreeMap::from([
+            ("aquamarine", reached("hyprland/aquamarine", &["hyprland"])),
+            ("hyprland", reached("hyprland", &[])),
+            // Followed by hyprland, but still a direct input.
+            ("nixpkgs", reached("nixpkgs", &["hyprland"])),
+            (
+                "nixpkgs_2",
+                reached("hyprland/aquamarine/nixpkgs", &["hyprland/aquamarine"]),
+            ),
+            (
+                "systems",
+                reached("hyprland/systems", &["hyprland", "hyprland/aquamarine"]),
+            ),
+        ]);
+        assert_eq!(lf.walk(true), all);
+    }
+
+    #[test]
+    fn github_compares_are_summarised() {
+        let commit = |message: &str, date: &str| {
+            format!(
+                r#"{{"sha":"x","commit":{{"message":"{message}","committer":{{"date":"{date}"}}}}}}"#
+            )
+        };
+        let commits: Vec<_> = (1..=7)
+            .map(|i| {
+                commit(
+                    &format!("Commit {i}\\n\\nDetails"),
+                    &format!("2024-07-0{i}T00:00:00Z"),
+                )
+            })
+            .collect();
+        let response = format!(
+            r#"{{"html_url":"https://github.com/nixos/nixpkgs/compare/a...b",
+            "status":"diverged","ahead_by":70000,"behind_by":1,"total_commits":70000,
+            "commits":[{}]}}"#,
+            commits.join(",")
+        );
+        let compare: GithubCompare = serde_json::from_str(&response).unwrap();
+        assert_eq!(
+            Update::from(compare),
+            Update::Valid {
+                html_url: "https://github.com/nixos/nixpkgs/compare/a...b".to_string(),
+                stale: false,
+                ahead_by: 70000,
+                behind_by: Some(1),
+                newest_commit_date: Some("2024-07-07T00:00:00Z".to_string()),
+                commits: (3..=7).rev().map(|i| format!("Commit {i}")).collect(),
+            }
+        );
+    }
+}
@@ -0,0 +1,207 @@
+//! The running NixOS system, compared with the lock file on disk, which is
+//! ahead of it after a `nix flake update` without a rebuild.
+
+use std::fs;
+use std::io::{self, Write};
+use std::path::PathBuf;
+use std::time::UNIX_EPOCH;
+
+use serde::Serialize;
+
+use super::{days_since, read_lockfile, NodeType};
+
+/// Where the running system is found, set for tests.
+#[derive(Debug, Clone)]
+pub struct Paths {
+    /// Links to the running system, e.g. `/run/current-system`.
+    pub current_system: PathBuf,
+    /// Has a `system-<generation>-link` per generation.
+    pub profiles: PathBuf,
+}
+
+impl Default for Paths {
+    fn default() -> Self {
+        Paths {
+            current_system: "/run/current-system".into(),
+            profiles: "/nix/var/nix/profiles".into(),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Serialize)]
+pub struct Running {
+    /// e.g. `24.11.20240707.5e4fbfb`
+    pub nixos_version: String,
+    /// The nixpkgs revision it was built from, abbreviated, if it's known.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub rev: Option<String>,
+    /// The lock file's nixpkgs revision.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub locked_rev: Option<String>,
+    /// Whether it was built from the lock file's nixpkgs, unknown without both
+    /// revisions.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub matches: Option<bool>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub generation: Option<u64>,
+    /// When the generation was created, as a unix timestamp.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub created: Option<u64>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub age_days: Option<u64>,
+}
+
+/// Writes how the running system compares with the lock file to `out`.
+pub fn nixos(paths: &Paths, lock_file: &str, out: &mut impl Write) -> io::Result<()> {
+    let running = match running(paths, lock_file) {
+        Ok(running) => serde_json::to_value(running)?,
+        Err(error) => serde_json::json!([{ "error": error }]),
+    };
+    writeln!(out, "{running}")
+}
+
+pub fn running(paths: &Paths, lock_file: &str) -> Result<Running, String> {
+    let version_file = paths.current_system.join("nixos-version");
+    let nixos_version = fs::read_to_string(&version_file)
+        .map_err(|e| format!("Unable to read {}: {e}", version_file.display()))?
+        .trim()
+        .to_string();
+    let rev = version_rev(&nixos_version).map(str::to_string);
+    let locked_rev = locked_nixpkgs(lock_file)?;
+    let matches = match (&rev, &locked_rev) {
+        (Some(rev), Some(locked)) => Some(locked.starts_with(rev.as_str())),
+        _ => None,
+    };
+
+    let link = generation_link(paths);
+    let generation = link.as_ref().map(|(generation, _)| *generation);
+    // The generation's link is made when it's built, the running system's
+    // when it's activated.
+    let link = link.map_or(paths.current_system.clone(), |(_, link)| link);
+    let created = fs::symlink_metadata(link)
+        .and_then(|m| m.modified())
+        .ok()
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs());
+    Ok(Running {
+        nixos_version,
+        rev,
+        locked_rev,
+        matches,
+        generation,
+        created,
+        age_days: created.map(days_since),
+    })
+}
+
+/// The nixpkgs revision at the end of a version, e.g. `5e4fbfb` in
+/// `24.11.20240707.5e4fbfb`. Versions not built from git, e.g. `24.11pre-git`,
+/// don't have one.
+fn version_rev(version: &str) -> Option<&str> {
+    let (_, rev) = version.rsplit_once('.')?;
+    (rev.len() >= 7 && rev.chars().all(|c| c.is_ascii_hexdigit())).then_some(rev)
+}
+
+/// The revision of the root's `nixpkgs` input.
+fn locked_nixpkgs(lock_file: &str) -> Result<Option<String>, String> {
+    let lockfile = read_lockfile(lock_file)?;
+    let node = lockfile
+        .inputs(&lockfile.root)
+        .and_then(|inputs| lockfile.resolve(inputs.get("nixpkgs")?, 0));
+    Ok(match node.and_then(|node| lockfile.nodes.get(node)) {
+        Some(NodeType::Node { locked, .. }) => locked.source.followed().1.map(str::to_string),
+        _ => None,
+    })
+}
+
+/// The generation of the running system, with its link in the profiles.
+fn generation_link(paths: &Paths) -> Option<(u64, PathBuf)> {
+    let running = fs::canonicalize(&paths.current_system).ok()?;
+    fs::read_dir(&paths.profiles)
+        .ok()?
+        .filter_map(|entry| {
+            let path = entry.ok()?.path();
+            let name = path.file_name()?.to_str()?;
+            let generation = name.strip_prefix("system-")?.strip_suffix("-link")?;
+            Some((generation.parse().ok()?, path))
+        })
+        .find(|(_, link): &(u64, PathBuf)| fs::canonicalize(link).is_ok_and(|l| l == running))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+
+    use super::*;
+
+    /// The paths of a fake system, for tests.
+    fn fake_system(dir: &Path, version: &str) -> Paths {
+        use std::os::unix::fs::symlink;
+
+        let store = dir.join("store");
+        let profiles = dir.join("profiles");
+        for (generation, version) in [(41, "24.05.20240601.0000000"), (42, version)] {
+            let system = store.join(format!("nixos-system-{generation}"));
+            fs::create_dir_all(&system).unwrap();
+            fs::write(system.join("nixos-version"), format!("{version}\n")).unwrap();
+            fs::create_dir_all(&profiles).unwrap();
+            symlink(&system, profiles.join(format!("system-{generation}-link"))).unwrap();
+        }
+        let current_system = dir.join("current-system");
+        symlink(store.join("nixos-system-42"), &current_system).unwrap();
+        Paths {
+            current_system,
+            profiles,
+        }
+    }
+
+    #[test]
+    fn revisions_are_read_from_versions() {
+        assert_eq!(version_rev("24.11.20240707.5e4fbfb"), Some("5e4fbfb"));
+        assert_eq!(version_rev("24.11pre-git"), None);
+        assert_eq!(version_rev("24.11.20240707.dirty"), None);
+    }
+
+    #[test]
+    fn the_running_system_is_compared_with_the_lock_file() {
+        let dir = std::env::temp_dir().join(format!("nasty-system-{}", std::process::id()));
+        let paths = fake_system(&dir, "24.11.20240707.5e4fbfb");
+        let lock_file = dir.join("flake.lock");
+        let lock = |rev: &str| {
+            format!(
+                r#"{{"nodes": {{
+                  "root": {{"inputs": {{"nixpkgs": "nixpkgs"}}}},
+                  "nixpkgs": {{
+                    "locked": {{"type": "github", "owner": "NixOS", "repo": "nixpkgs", "rev": "{rev}"}},
+                    "original": {{"type": "github", "owner": "NixOS", "repo": "nixpkgs"}}
+                  }}
+                }}, "root": "root", "version": 7}}"#
+            )
+        };
+        let lock_path = lock_file.to_str().unwrap();
+
+        fs::write(&lock_file, lock("5e4fbfb0d1b2c3")).unwrap();
+        let running = running(&paths, lock_path).unwrap();
+        assert_eq!(running.rev.as_deref(), Some("5e4fbfb"));
+        assert_eq!(running.matches, Some(true));
+        assert_eq!(running.generation, Some(42));
+        assert_eq!(running.age_days, Some(0));
+
+        // Updated, but not rebuilt.
+        fs::write(&lock_file, lock("e8c38b73aeb2")).unwrap();
+        let running = super::running(&paths, lock_path).unwrap();
+        assert_eq!(running.locked_rev.as_deref(), Some("e8c38b73aeb2"));
+        assert_eq!(running.matches, Some(false));
+
+        let mut out = vec![];
+        let missing = Paths {
+            current_system: dir.join("missing"),
+            ..paths
+        };
+        nixos(&missing, lock_path, &mut out).unwrap();
+        assert!(String::from_utf8(out)
+            .unwrap()
+            .starts_with(r#"[{"error":"Unable to read"#));
+        fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -1,53 +1,452 @@
-use std::io::BufRead;
-use std::net::Shutdown;
+use std::io::{BufRead, Read, Write};
 use std::os::unix::net::UnixStream;
 use std::{env, io::BufReader};
 
-use super::internal::{WorkspaceChange, WorkspaceState};
+use anyhow::{bail, Context};
+use serde::Deserialize;
 
-fn _listen_and_print(stream: BufReader<UnixStream>) {
-    let mut state = WorkspaceState::new();
-    for line in stream.lines() {
-        let Ok(msg) = line else {
-            continue;
+use super::internal::{Change, CompositorChange, WindowChange, WorkspaceChange, WorkspaceInfo};
+use super::{Action, Subscriptions, Target, WindowManager};
+
+/// Directory containing the IPC sockets of the running Hyprland instance.
+fn socket_dir() -> Result<String, env::VarError> {
+    let hypr_id = env::var("HYPRLAND_INSTANCE_SIGNATURE")?;
+    let runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
+        let uid = env::var("UID").unwrap_or("1000".into());
+        format!("/run/user/{uid}")
+    });
+    Ok(format!("{runtime_dir}/hypr/{hypr_id}"))
+}
+
+/// Sends a single request to Hyprland's command socket (`.socket.sock`),
+/// e.g. `j/activewindow`, and returns the raw response.
+fn request(cmd: &str) -> std::io::Result<String> {
+    let dir = socket_dir().map_err(|e| std::io::Error::new(std::io::ErrorKind::NotFound, e))?;
+    let addr = format!("{dir}/.socket.sock");
+    let mut stream = UnixStream::connect(addr)?;
+    stream.write_all(cmd.as_bytes())?;
+    let mut response = String::new();
+    stream.read_to_string(&mut response)?;
+    Ok(response)
+}
+
+/// Splits a socket2 line (`EVENT>>DATA`) into its event name and data.
+fn parse_event(line: &str) -> Option<(&str, &str)> {
+    line.split_once(">>")
+}
+
+/// Hyprland's IPC, see <https://wiki.hyprland.org/IPC/>.
+pub struct Hyprland {
+    events: BufReader<UnixStream>,
+    subs: Subscriptions,
+}
+
+#[derive(Debug, Deserialize)]
+struct HyprWorkspace {
+    id: i64,
+    #[serde(default)]
+    name: String,
+    #[serde(default)]
+    monitor: String,
+    #[serde(default)]
+    windows: u32,
+}
+
+impl HyprWorkspace {
+    fn query_all() -> anyhow::Result<Vec<HyprWorkspace>> {
+        Ok(serde_json::from_str(&request("j/workspaces")?)?)
+    }
+
+    fn describe(self) -> [WorkspaceChange; 2] {
+        let info = WorkspaceInfo {
+            name: Some(self.name).filter(|name| *name != self.id.to_string()),
+            output: Some(self.monitor).filter(|m| !m.is_empty()),
+            windows: Some(self.windows),
+            ..Default::default()
         };
-        let content = msg.split(">>").collect::<Vec<_>>();
-        let [opcode, id] = content[..] else {
-            continue;
+        [
+            WorkspaceChange::Create(self.id),
+            WorkspaceChange::Describe(self.id, info),
+        ]
+    }
+}
+
+/// Events after which the workspaces' monitors or window counts are re-read.
+const DESCRIBE_AFTER: [&str; 6] = [
+    "createworkspace",
+    "moveworkspace",
+    "renameworkspace",
+    "openwindow",
+    "closewindow",
+    "movewindow",
+];
+
+impl Hyprland {
+    /// Connects to the event socket (`.socket2.sock`), which has the events
+    /// of every topic.
+    pub fn connect(subs: Subscriptions) -> anyhow::Result<Hyprland> {
+        let dir =
+            socket_dir().context("HYPRLAND_INSTANCE_SIGNATURE isn't set, is Hyprland running?")?;
+        let stream = UnixStream::connect(format!("{dir}/.socket2.sock"))?;
+        Ok(Hyprland {
+            events: BufReader::new(stream),
+            subs,
+        })
+    }
+}
+
+/// Translates a socket2 event into changes to the workspaces.
+///
+/// Only the v2 events (`ID,NAME`) have the id, the older ones only have the
+/// name, which isn't the id of named or special workspaces.
+fn workspace_changes(opcode: &str, data: &str) -> Vec<WorkspaceChange> {
+    let id = || data.split_once(',')?.0.parse::<i64>().ok();
+    let change: fn(i64) -> WorkspaceChange = match opcode {
+        "workspacev2" => WorkspaceChange::Focus,
+        "createworkspacev2" => WorkspaceChange::Create,
+        "destroyworkspacev2" => WorkspaceChange::Destroy,
+        _ => return vec![],
+    };
+    match id() {
+        Some(id) => vec![change(id)],
+        None => {
+            eprintln!("Got a {opcode} event without an id: {data}");
+            vec![]
+        }
+    }
+}
+
+/// The workspaces as they are right now.
+fn workspace_snapshot() -> anyhow::Result<Vec<WorkspaceChange>> {
+    let workspaces = HyprWorkspace::query_all()?;
+    let active: HyprWorkspace = serde_json::from_str(&request("j/activeworkspace")?)?;
+    let mut changes: Vec<_> = workspaces
+        .into_iter()
+        .flat_map(HyprWorkspace::describe)
+        .collect();
+    changes.push(WorkspaceChange::Focus(active.id));
+    Ok(changes)
+}
+
+impl WindowManager for Hyprland {
+    type Change = Change;
+
+    fn snapshot(&mut self) -> anyhow::Result<Vec<Change>> {
+        let mut changes = vec![];
+        if self.subs.workspaces {
+            changes.extend(workspace_snapshot()?.into_iter().map(Change::from));
+        }
+        changes.extend(window_snapshot(self.subs));
+        Ok(changes)
+    }
+
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<Change>>> {
+        let mut line = String::new();
+        if self.events.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let Some((opcode, data)) = parse_event(line.trim_end()) else {
+            return Ok(Some(vec![]));
         };
-        let id = id.parse::<i64>().unwrap_or(0);
-        match opcode {
-            "workspace" => {
-                state.update(WorkspaceChange::Focus(id));
+        let mut changes: Vec<Change> = vec![];
+        if self.subs.workspaces {
+            changes.extend(
+                workspace_changes(opcode, data)
+                    .into_iter()
+                    .map(Change::from),
+            );
+            if DESCRIBE_AFTER.contains(&opcode) {
+                for ws in HyprWorkspace::query_all()? {
+                    changes.extend(ws.describe().map(Change::from));
+                }
             }
-            "createworkspace" => {
-                state.update(WorkspaceChange::Create(id));
-            }
-            "destroyworkspace" => {
-                state.update(WorkspaceChange::Destroy(id));
+        }
+        if self.subs.window {
+            changes.extend(window_changes(opcode, data).into_iter().map(Change::from));
+            if opcode == "activewindow" {
+                // The event doesn't say if the newly focused window is fullscreen.
+                let fullscreen = ActiveWindow::query().is_some_and(|w| w.is_fullscreen());
+                changes.push(WindowChange::Fullscreen(fullscreen).into());
             }
-            _ => continue,
         }
-        if let Ok(data) = serde_json::to_string(&state) {
-            println!("{}", data);
+        if self.subs.compositor {
+            changes.extend(
+                compositor_changes(opcode, data)
+                    .into_iter()
+                    .map(Change::from),
+            );
         }
+        Ok(Some(changes))
     }
 }
-pub fn listen_and_print() {
-    let hypr_id = env::var("HYPRLAND_INSTANCE_SIGNATURE").expect("Is Hyprland running?");
-    let runtime_dir = env::var("XDG_RUNTIME_DIR").unwrap_or_else(|_| {
-        let uid = env::var("UID").unwrap_or("1000".into());
-        format!("/run/user/{uid}")
-    });
-    let addr = format!("{runtime_dir}/hypr/{hypr_id}/.socket2.sock");
-    let u_stream = UnixStream::connect(addr).expect("Couldn't connect to the server...");
-    let stream = BufReader::new(u_stream.try_clone().expect("Couldn't clone socket"));
-    ctrlc::set_handler(move || {
-        u_stream
-            .shutdown(Shutdown::Read)
-            .expect("shutdown function failed");
-        println!("Closing socket reader.")
+
+/// The workspace in dispatcher syntax, see the wiki's "Dispatchers" page.
+fn workspace_arg(target: &Target) -> String {
+    match target {
+        Target::Id(id) => id.to_string(),
+        Target::Name(name) => format!("name:{name}"),
+    }
+}
+
+/// The `dispatch` request performing `action`.
+///
+/// `renameworkspace` only takes ids, names have to be resolved first.
+fn dispatch(action: &Action) -> anyhow::Result<String> {
+    Ok(match action {
+        // Hyprland creates workspaces by switching to them.
+        Action::Focus(ws) | Action::Create(ws) => {
+            format!("dispatch workspace {}", workspace_arg(ws))
+        }
+        Action::MoveWindow(ws) => format!("dispatch movetoworkspace {}", workspace_arg(ws)),
+        Action::Rename(Target::Id(id), name) => format!("dispatch renameworkspace {id} {name}"),
+        Action::Rename(Target::Name(name), _) => bail!("No workspace is named {name:?}."),
     })
-    .expect("Error setting Ctrl-C handler");
-    _listen_and_print(stream);
+}
+
+/// Performs `action` through Hyprland's command socket.
+pub fn run(action: &Action) -> anyhow::Result<()> {
+    let mut action = action.clone();
+    if let Action::Rename(target @ Target::Name(_), _) = &mut action {
+        let workspaces = HyprWorkspace::query_all()?;
+        if let Some(ws) = workspaces
+            .iter()
+            .find(|ws| Target::Name(ws.name.clone()) == *target)
+        {
+            *target = Target::Id(ws.id);
+        }
+    }
+    let response = request(&dispatch(&action)?)?;
+    if response.trim() != "ok" {
+        bail!("Hyprland refused the request: {}", response.trim());
+    }
+    Ok(())
+}
+
+#[derive(Debug, Deserialize)]
+struct ActiveWindow {
+    #[serde(default)]
+    class: String,
+    #[serde(default)]
+    title: String,
+    workspace: Option<ActiveWindowWorkspace>,
+    /// A bool on older Hyprland versions, a fullscreen mode (0-2) on newer ones.
+    #[serde(default)]
+    fullscreen: serde_json::Value,
+    #[serde(default)]
+    floating: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct ActiveWindowWorkspace {
+    name: String,
+}
+
+impl ActiveWindow {
+    fn query() -> Option<ActiveWindow> {
+        serde_json::from_str(&request("j/activewindow").ok()?).ok()
+    }
+
+    fn is_fullscreen(&self) -> bool {
+        match &self.fullscreen {
+            serde_json::Value::Bool(b) => *b,
+            serde_json::Value::Number(n) => n.as_u64().unwrap_or(0) > 0,
+            _ => false,
+        }
+    }
+}
+
+/// Translates a socket2 event into changes to the focused window.
+fn window_changes(opcode: &str, data: &str) -> Vec<WindowChange> {
+    match opcode {
+        "activewindow" => {
+            let (class, title) = data.split_once(',').unwrap_or((data, ""));
+            vec![WindowChange::Focus {
+                class: class.to_string(),
+                title: title.to_string(),
+            }]
+        }
+        "fullscreen" => vec![WindowChange::Fullscreen(data == "1")],
+        "workspace" => vec![WindowChange::Workspace(data.to_string())],
+        _ => vec![],
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct Devices {
+    keyboards: Vec<Keyboard>,
+}
+
+#[derive(Debug, Deserialize)]
+struct Keyboard {
+    #[serde(default)]
+    main: bool,
+    active_keymap: String,
+}
+
+/// The main keyboard's layout.
+fn active_layout() -> Option<String> {
+    let devices: Devices = serde_json::from_str(&request("j/devices").ok()?).ok()?;
+    let keyboard = devices
+        .keyboards
+        .iter()
+        .find(|k| k.main)
+        .or(devices.keyboards.first())?;
+    Some(keyboard.active_keymap.clone())
+}
+
+/// Translates a socket2 event into changes to the compositor state.
+fn compositor_changes(opcode: &str, data: &str) -> Vec<CompositorChange> {
+    match opcode {
+        "submap" => vec![CompositorChange::Submap(data.to_string())],
+        // `KEYBOARD,LAYOUT`
+        "activelayout" => match data.split_once(',') {
+            Some((_, layout)) => vec![CompositorChange::Layout(layout.to_string())],
+            None => vec![],
+        },
+        // `WORKSPACE,MONITOR`, the workspace is empty once it's closed.
+        "activespecial" => {
+            let (name, _) = data.rsplit_once(',').unwrap_or((data, ""));
+            vec![CompositorChange::Special(name.to_string())]
+        }
+        "fullscreen" => vec![CompositorChange::Fullscreen(data == "1")],
+        // `ADDRESS,FLOATING`
+        "changefloatingmode" => vec![CompositorChange::Floating(data.ends_with(",1"))],
+        _ => vec![],
+    }
+}
+
+/// The focused window, and the keyboard layout, fullscreen and floating state
+/// as they are right now.
+///
+/// Hyprland can't be asked for the active submap, it's empty until the
+/// first `submap` event.
+fn window_snapshot(subs: Subscriptions) -> Vec<Change> {
+    let mut changes = vec![];
+    if !subs.window && !subs.compositor {
+        return changes;
+    }
+    let window = ActiveWindow::query();
+    if let (true, Some(window)) = (subs.window, &window) {
+        changes.push(WindowChange::Fullscreen(window.is_fullscreen()).into());
+        if let Some(ws) = &window.workspace {
+            changes.push(WindowChange::Workspace(ws.name.clone()).into());
+        }
+        changes.push(
+            WindowChange::Focus {
+                class: window.class.clone(),
+                title: window.title.clone(),
+            }
+            .into(),
+        );
+    }
+    if subs.compositor {
+        if let Some(layout) = active_layout() {
+            changes.push(CompositorChange::Layout(layout).into());
+        }
+        if let Some(window) = &window {
+            changes.push(CompositorChange::Fullscreen(window.is_fullscreen()).into());
+            changes.push(CompositorChange::Floating(window.floating).into());
+        }
+    }
+    changes
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::internal::{CompositorState, WindowState};
+    use super::*;
+
+    #[test]
+    fn events_are_split_on_the_first_delimiter() {
+        assert_eq!(
+            parse_event("activewindow>>kitty,a >> b"),
+            Some(("activewindow", "kitty,a >> b"))
+        );
+        assert_eq!(parse_event("garbage"), None);
+    }
+
+    #[test]
+    fn workspace_events_are_translated() {
+        assert_eq!(
+            workspace_changes("createworkspacev2", "4,4"),
+            vec![WorkspaceChange::Create(4)]
+        );
+        assert_eq!(
+            workspace_changes("workspacev2", "7,chat"),
+            vec![WorkspaceChange::Focus(7)]
+        );
+        assert_eq!(
+            workspace_changes("destroyworkspacev2", "-98,special:magic"),
+            vec![WorkspaceChange::Destroy(-98)]
+        );
+        // Names with commas are still split on the first one.
+        assert_eq!(
+            workspace_changes("workspacev2", "8,a,b"),
+            vec![WorkspaceChange::Focus(8)]
+        );
+        // The older events are sent along with these, without the id.
+        assert_eq!(workspace_changes("workspace", "chat"), vec![]);
+        assert_eq!(workspace_changes("openwindow", "0x1,4,kitty,~"), vec![]);
+    }
+
+    #[test]
+    fn actions_are_dispatched() {
+        let dispatched = |action| dispatch(&action).unwrap();
+        assert_eq!(
+            dispatched(Action::Focus(Target::Id(3))),
+            "dispatch workspace 3"
+        );
+        assert_eq!(
+            dispatched(Action::Create(Target::Name("chat".into()))),
+            "dispatch workspace name:chat"
+        );
+        assert_eq!(
+            dispatched(Action::MoveWindow(Target::Id(2))),
+            "dispatch movetoworkspace 2"
+        );
+        assert_eq!(
+            dispatched(Action::Rename(Target::Id(2), "code".into())),
+            "dispatch renameworkspace 2 code"
+        );
+        assert!(dispatch(&Action::Rename(Target::Name("web".into()), "code".into())).is_err());
+    }
+
+    #[test]
+    fn window_titles_may_contain_commas() {
+        let mut state = WindowState::new();
+        for change in window_changes("activewindow", "firefox,Hello, World") {
+            state.update(change);
+        }
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"class":"firefox","title":"Hello, World","workspace":"","fullscreen":false}"#
+        );
+    }
+
+    #[test]
+    fn compositor_events_are_translated() {
+        let mut state = CompositorState::new();
+        for (opcode, data) in [
+            ("submap", "resize"),
+            ("activelayout", "at-translated-set-2-keyboard,English (US)"),
+            ("activespecial", "special:scratch,DP-1"),
+            ("fullscreen", "1"),
+            ("changefloatingmode", "5649ab26c5e0,1"),
+            ("openwindow", "5649ab26c5e0,1,kitty,~"),
+        ] {
+            for change in compositor_changes(opcode, data) {
+                state.update(change);
+            }
+        }
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"submap":"resize","layout":"English (US)","special":"special:scratch","fullscreen":true,"floating":true}"#
+        );
+        for change in compositor_changes("activespecial", ",DP-1") {
+            state.update(change);
+        }
+        assert!(serde_json::to_string(&state)
+            .unwrap()
+            .contains(r#""special":"","#));
+    }
 }
@@ -0,0 +1,347 @@
+use std::env;
+use std::os::unix::net::UnixStream;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use anyhow::{bail, Context};
+use swayipc::{Connection, EventStream};
+
+use super::internal::{Change, WorkspaceChange, WorkspaceInfo};
+use super::sway::{event_types, next_ipc_event, run_command, window_changes, window_snapshot};
+use super::{Action, Subscriptions, WindowManager};
+
+/// i3's IPC, see <https://i3wm.org/docs/ipc.html>.
+///
+/// Sway speaks the same protocol, but i3 identifies workspaces by `num`
+/// (`-1` for names without a leading number) rather than by their name.
+pub struct I3 {
+    conn: Connection,
+    events: EventStream,
+    subs: Subscriptions,
+}
+
+/// Finds i3's IPC socket from `I3SOCK`, falling back to asking i3.
+fn socket_path() -> anyhow::Result<PathBuf> {
+    if let Ok(path) = env::var("I3SOCK") {
+        if !path.is_empty() {
+            return Ok(PathBuf::from(path));
+        }
+    }
+    let out = Command::new("i3")
+        .arg("--get-socketpath")
+        .output()
+        .context("I3SOCK isn't set and `i3 --get-socketpath` couldn't be run, is i3 running?")?;
+    if !out.status.success() {
+        bail!(
+            "I3SOCK isn't set and `i3 --get-socketpath` failed: {}",
+            String::from_utf8_lossy(&out.stderr).trim()
+        );
+    }
+    Ok(PathBuf::from(String::from_utf8(out.stdout)?.trim()))
+}
+
+impl I3 {
+    pub fn connect(subs: Subscriptions) -> anyhow::Result<I3> {
+        I3::connect_to(&socket_path()?, subs)
+    }
+
+    pub fn connect_to(path: &Path, subs: Subscriptions) -> anyhow::Result<I3> {
+        let conn = Connection::from(UnixStream::connect(path)?);
+        let events =
+            Connection::from(UnixStream::connect(path)?).subscribe(event_types(subs, false))?;
+        Ok(I3 { conn, events, subs })
+    }
+}
+
+/// Numbered workspaces use their number, others fall back to the con id.
+fn workspace_id(num: Option<i32>, id: i64) -> i64 {
+    match num {
+        Some(num) if num >= 0 => num.into(),
+        _ => id,
+    }
+}
+
+fn describe(id: i64, name: Option<String>, output: Option<String>) -> WorkspaceChange {
+    WorkspaceChange::Describe(
+        id,
+        WorkspaceInfo {
+            name,
+            output,
+            ..Default::default()
+        },
+    )
+}
+
+impl I3 {
+    fn workspace_snapshot(&mut self) -> anyhow::Result<Vec<WorkspaceChange>> {
+        let mut changes = vec![];
+        let mut focused = None;
+        for ws in self.conn.get_workspaces()? {
+            let id = workspace_id(Some(ws.num), ws.id);
+            changes.push(WorkspaceChange::Create(id));
+            changes.push(describe(id, Some(ws.name), Some(ws.output)));
+            if ws.focused {
+                focused = Some(id);
+            }
+        }
+        changes.extend(focused.map(WorkspaceChange::Focus));
+        Ok(changes)
+    }
+
+    /// Translates an IPC event into changes to the workspaces.
+    fn workspace_changes(&mut self, event: swayipc::Event) -> anyhow::Result<Vec<WorkspaceChange>> {
+        let swayipc::Event::Workspace(ws) = event else {
+            return Ok(vec![]);
+        };
+        match ws.change {
+            // Renaming can change a workspace's number, re-read everything.
+            swayipc::WorkspaceChange::Rename | swayipc::WorkspaceChange::Reload => {
+                let mut changes = vec![WorkspaceChange::Reset];
+                changes.extend(self.workspace_snapshot()?);
+                return Ok(changes);
+            }
+            swayipc::WorkspaceChange::Init
+            | swayipc::WorkspaceChange::Empty
+            | swayipc::WorkspaceChange::Focus
+            | swayipc::WorkspaceChange::Move => (),
+            _ => return Ok(vec![]),
+        }
+        let Some(node) = ws.current else {
+            eprintln!("Got a {:?} event with no workspace...?", ws.change);
+            return Ok(vec![]);
+        };
+        let id = workspace_id(node.num, node.id);
+        let changes = match ws.change {
+            swayipc::WorkspaceChange::Init => {
+                vec![
+                    WorkspaceChange::Create(id),
+                    describe(id, node.name, node.output),
+                ]
+            }
+            swayipc::WorkspaceChange::Empty => vec![WorkspaceChange::Destroy(id)],
+            swayipc::WorkspaceChange::Move => vec![describe(id, node.name, node.output)],
+            _ => vec![
+                WorkspaceChange::Focus(id),
+                describe(id, node.name, node.output),
+            ],
+        };
+        Ok(changes)
+    }
+}
+
+impl WindowManager for I3 {
+    type Change = Change;
+
+    fn snapshot(&mut self) -> anyhow::Result<Vec<Change>> {
+        let mut changes = vec![];
+        if self.subs.workspaces {
+            changes.extend(self.workspace_snapshot()?.into_iter().map(Change::from));
+        }
+        changes.extend(window_snapshot(&mut self.conn, self.subs)?);
+        Ok(changes)
+    }
+
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<Change>>> {
+        let Some(event) = next_ipc_event(&mut self.events)? else {
+            return Ok(None);
+        };
+        let mut changes = window_changes(&mut self.conn, &event, self.subs)?;
+        if self.subs.workspaces {
+            changes.extend(self.workspace_changes(event)?.into_iter().map(Change::from));
+        }
+        Ok(Some(changes))
+    }
+}
+
+pub fn run(action: &Action) -> anyhow::Result<()> {
+    let mut conn = Connection::from(UnixStream::connect(socket_path()?)?);
+    run_command(&mut conn, action, |ws| workspace_id(Some(ws.num), ws.id))
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{Read, Write};
+    use std::os::unix::net::UnixListener;
+    use std::thread;
+
+    use super::super::internal::{WindowChange, WindowState};
+    use super::*;
+
+    const MAGIC: &[u8] = b"i3-ipc";
+    const GET_WORKSPACES: u32 = 1;
+    const SUBSCRIBE: u32 = 2;
+    const GET_TREE: u32 = 4;
+    const WORKSPACE_EVENT: u32 = 0x8000_0000;
+    const WINDOW_EVENT: u32 = 0x8000_0003;
+
+    fn read_msg(stream: &mut UnixStream) -> Option<u32> {
+        let mut header = [0_u8; 14];
+        stream.read_exact(&mut header).ok()?;
+        assert_eq!(&header[..6], MAGIC);
+        let len = u32::from_ne_bytes(header[6..10].try_into().unwrap());
+        let kind = u32::from_ne_bytes(header[10..14].try_into().unwrap());
+        let mut payload = vec![0_u8; len as usize];
+        stream.read_exact(&mut payload).ok()?;
+        Some(kind)
+    }
+
+    fn write_msg(stream: &mut UnixStream, kind: u32, payload: &str) {
+        let mut msg = MAGIC.to_vec();
+        msg.extend((payload.len() as u32).to_ne_bytes());
+        msg.extend(kind.to_ne_bytes());
+        msg.extend(payload.as_bytes());
+        stream.write_all(&msg).unwrap();
+    }
+
+    fn workspace(id: i64, num: i32, name: &str, focused: bool) -> String {
+        format!(
+            r#"{{"id":{id},"num":{num},"name":"{name}","visible":{focused},"focused":{focused},
+            "urgent":false,"rect":{{"x":0,"y":0,"width":1920,"height":1080}},"output":"eDP-1"}}"#
+        )
+    }
+
+    fn workspace_event(change: &str, id: i64, num: i32, name: &str) -> String {
+        let rect = r#"{"x":0,"y":0,"width":1920,"height":1080}"#;
+        format!(
+            r#"{{"change":"{change}","old":null,"current":{{"id":{id},"num":{num},"name":"{name}",
+            "type":"workspace","border":"normal","current_border_width":-1,"layout":"splith",
+            "percent":null,"rect":{rect},"window_rect":{rect},"deco_rect":{rect},
+            "geometry":{rect},"urgent":false,"focused":false,"focus":[],"nodes":[],
+            "floating_nodes":[],"sticky":false,"output":"eDP-1"}}}}"#
+        )
+    }
+
+    /// A container, e.g. a window when it has a `pid`.
+    fn node(id: i64, kind: &str, name: &str, focused: bool, pid: Option<i32>) -> String {
+        let rect = r#"{"x":0,"y":0,"width":1920,"height":1080}"#;
+        let pid = pid.map_or("null".to_string(), |pid| pid.to_string());
+        format!(
+            r#"{{"id":{id},"name":"{name}","type":"{kind}","border":"normal",
+            "current_border_width":-1,"layout":"splith","percent":null,"rect":{rect},
+            "window_rect":{rect},"deco_rect":{rect},"geometry":{rect},"urgent":false,
+            "focused":{focused},"focus":[],"nodes":[],"floating_nodes":[],"sticky":false,
+            "pid":{pid},"window_properties":{{"class":"{name}"}}}}"#
+        )
+    }
+
+    /// Serves `workspaces` on the command connection and sends `events` to the
+    /// subscribed connection before hanging up.
+    fn fake_i3(name: &str, workspaces: String, events: Vec<String>) -> PathBuf {
+        let events = events.into_iter().map(|e| (WORKSPACE_EVENT, e)).collect();
+        fake_ipc(name, vec![(GET_WORKSPACES, workspaces)], events)
+    }
+
+    /// Answers each request on the command connection with the reply for its
+    /// kind, and sends `events` to the subscribed connection before hanging up.
+    fn fake_ipc(name: &str, replies: Vec<(u32, String)>, events: Vec<(u32, String)>) -> PathBuf {
+        let path = env::temp_dir().join(format!("nasty-{name}-{}.sock", std::process::id()));
+        let _ = std::fs::remove_file(&path);
+        let listener = UnixListener::bind(&path).unwrap();
+        thread::spawn(move || {
+            let (mut cmd, _) = listener.accept().unwrap();
+            thread::spawn(move || {
+                while let Some(kind) = read_msg(&mut cmd) {
+                    let (_, reply) = replies.iter().find(|(k, _)| *k == kind).unwrap();
+                    write_msg(&mut cmd, kind, reply);
+                }
+            });
+            let (mut sub, _) = listener.accept().unwrap();
+            assert_eq!(read_msg(&mut sub), Some(SUBSCRIBE));
+            write_msg(&mut sub, SUBSCRIBE, r#"{"success":true}"#);
+            for (kind, event) in events {
+                write_msg(&mut sub, kind, &event);
+            }
+        });
+        path
+    }
+
+    fn run(path: &Path) -> Vec<String> {
+        let mut out = Vec::new();
+        let mut wm = I3::connect_to(path, Subscriptions::WORKSPACES).unwrap();
+        super::super::listen(&mut wm, &Default::default(), &mut out).unwrap();
+        let _ = std::fs::remove_file(path);
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_uses_num_and_keeps_names() {
+        let workspaces = format!(
+            "[{},{}]",
+            workspace(94, 1, "1: web", true),
+            workspace(95, -1, "chat", false)
+        );
+        let path = fake_i3("i3-snapshot", workspaces, vec![]);
+        assert_eq!(
+            run(&path),
+            vec![
+                r#"{"active_workspace":1,"workspaces":[1,95],"info":{"1":{"name":"1: web","output":"eDP-1"},"95":{"name":"chat","output":"eDP-1"}}}"#
+            ]
+        );
+    }
+
+    #[test]
+    fn events_update_the_state() {
+        let workspaces = format!("[{}]", workspace(94, 1, "1", true));
+        let events = vec![
+            workspace_event("init", 96, 2, "2: code"),
+            workspace_event("focus", 96, 2, "2: code"),
+            workspace_event("urgent", 96, 2, "2: code"),
+            workspace_event("empty", 94, 1, "1"),
+        ];
+        let path = fake_i3("i3-events", workspaces, events);
+        assert_eq!(
+            run(&path),
+            vec![
+                r#"{"active_workspace":1,"workspaces":[1],"info":{"1":{"name":"1","output":"eDP-1"}}}"#,
+                r#"{"active_workspace":1,"workspaces":[1,2],"info":{"1":{"name":"1","output":"eDP-1"},"2":{"name":"2: code","output":"eDP-1"}}}"#,
+                r#"{"active_workspace":2,"workspaces":[1,2],"info":{"1":{"name":"1","output":"eDP-1"},"2":{"name":"2: code","output":"eDP-1"}}}"#,
+                r#"{"active_workspace":2,"workspaces":[2],"info":{"2":{"name":"2: code","output":"eDP-1"}}}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn closing_the_last_window_clears_the_focus() {
+        let workspaces = format!("[{}]", workspace(94, 1, "1", true));
+        // Only the workspace is left, focused.
+        let tree = node(1, "root", "root", false, None).replace(
+            r#""nodes":[]"#,
+            &format!(r#""nodes":[{}]"#, node(94, "workspace", "1", true, None)),
+        );
+        let close = format!(
+            r#"{{"change":"close","container":{}}}"#,
+            node(7, "con", "firefox", false, Some(42))
+        );
+        let path = fake_ipc(
+            "i3-close",
+            vec![(GET_WORKSPACES, workspaces), (GET_TREE, tree)],
+            vec![(WINDOW_EVENT, close)],
+        );
+        let subs = Subscriptions {
+            workspaces: false,
+            window: true,
+            compositor: false,
+        };
+        let mut wm = I3::connect_to(&path, subs).unwrap();
+        let mut state = WindowState::new();
+        state.update(WindowChange::Focus {
+            class: "firefox".to_string(),
+            title: "firefox".to_string(),
+        });
+        for change in wm.next_event().unwrap().unwrap() {
+            let Change::Window(change) = change else {
+                panic!("Only the window is followed");
+            };
+            state.update(change);
+        }
+        let _ = std::fs::remove_file(&path);
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"class":"","title":"","workspace":"1","fullscreen":false}"#
+        );
+    }
+}
@@ -1,20 +1,45 @@
 // Internal, standarized, representation of multiple
 // Window managers' workspaces.
 
-use std::collections::BTreeSet;
+use std::collections::{BTreeMap, BTreeSet};
 
 use serde::Serialize;
 
-#[derive(Debug, Serialize)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
 pub struct WorkspaceState {
     active_workspace: i64,
     workspaces: BTreeSet<i64>,
+    /// Extra details for WMs that report them, keyed by workspace id.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    info: BTreeMap<i64, WorkspaceInfo>,
 }
 
+/// Optional details about a workspace.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct WorkspaceInfo {
+    /// The full name, for WMs where it differs from the id (e.g. i3's `1: web`).
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    /// The monitor the workspace is on.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+    /// Position of the workspace on its output, for WMs with per-output numbering.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub index: Option<u32>,
+    /// Number of windows on the workspace.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub windows: Option<u32>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
 pub enum WorkspaceChange {
     Destroy(i64),
     Create(i64),
     Focus(i64),
+    /// Replaces the details of a workspace.
+    Describe(i64, WorkspaceInfo),
+    /// Forgets every workspace, used before re-reading the full state.
+    Reset,
 }
 
 impl Default for WorkspaceState {
@@ -28,17 +53,207 @@ impl WorkspaceState {
         WorkspaceState {
             active_workspace: 0,
             workspaces: BTreeSet::new(),
+            info: BTreeMap::new(),
         }
     }
+
+    pub fn active_workspace(&self) -> i64 {
+        self.active_workspace
+    }
+
+    pub fn workspaces(&self) -> &BTreeSet<i64> {
+        &self.workspaces
+    }
+
+    pub fn info(&self, id: i64) -> Option<&WorkspaceInfo> {
+        self.info.get(&id)
+    }
+
     pub fn update(&mut self, optcode: WorkspaceChange) -> bool {
         match optcode {
             WorkspaceChange::Create(id) => self.workspaces.insert(id),
-            WorkspaceChange::Destroy(id) => self.workspaces.remove(&id),
+            WorkspaceChange::Destroy(id) => {
+                self.info.remove(&id);
+                self.workspaces.remove(&id)
+            }
             WorkspaceChange::Focus(id) => {
                 self.active_workspace = id;
                 // Adds the workspaces when they exist before this program starts
                 !self.workspaces.insert(id)
             }
+            WorkspaceChange::Describe(id, info) => self.info.insert(id, info.clone()) != Some(info),
+            WorkspaceChange::Reset => {
+                let changed = !self.workspaces.is_empty();
+                self.workspaces.clear();
+                self.info.clear();
+                changed
+            }
         }
     }
 }
+
+/// The currently focused window, as reported by the window manager.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct WindowState {
+    class: String,
+    title: String,
+    workspace: String,
+    fullscreen: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum WindowChange {
+    /// A new window gained focus, or the focused window changed its title.
+    Focus {
+        class: String,
+        title: String,
+    },
+    Workspace(String),
+    Fullscreen(bool),
+}
+
+impl WindowState {
+    pub fn new() -> WindowState {
+        WindowState::default()
+    }
+
+    /// Applies the change, returns `true` if the state was modified.
+    pub fn update(&mut self, optcode: WindowChange) -> bool {
+        let old = self.clone();
+        match optcode {
+            WindowChange::Focus { class, title } => {
+                self.class = class;
+                self.title = title;
+            }
+            WindowChange::Workspace(name) => self.workspace = name,
+            WindowChange::Fullscreen(fullscreen) => self.fullscreen = fullscreen,
+        }
+        old != *self
+    }
+}
+
+/// Compositor modes and input state, e.g. for showing a resize mode or the
+/// keyboard layout on a bar.
+#[derive(Debug, Default, Clone, PartialEq, Eq, Serialize)]
+pub struct CompositorState {
+    /// Hyprland's submap or Sway's binding mode, empty for the default one.
+    submap: String,
+    /// The active keyboard layout.
+    layout: String,
+    /// The open special workspace, empty if there's none.
+    special: String,
+    fullscreen: bool,
+    /// Whether the last window to change its floating mode is floating.
+    floating: bool,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CompositorChange {
+    Submap(String),
+    Layout(String),
+    Special(String),
+    Fullscreen(bool),
+    Floating(bool),
+}
+
+impl CompositorState {
+    pub fn new() -> CompositorState {
+        CompositorState::default()
+    }
+
+    /// Applies the change, returns `true` if the state was modified.
+    pub fn update(&mut self, optcode: CompositorChange) -> bool {
+        let old = self.clone();
+        match optcode {
+            // Both compositors call the default mode "default".
+            CompositorChange::Submap(name) if name == "default" => self.submap.clear(),
+            CompositorChange::Submap(name) => self.submap = name,
+            CompositorChange::Layout(layout) => self.layout = layout,
+            CompositorChange::Special(name) => self.special = name,
+            CompositorChange::Fullscreen(fullscreen) => self.fullscreen = fullscreen,
+            CompositorChange::Floating(floating) => self.floating = floating,
+        }
+        old != *self
+    }
+}
+
+/// A change to any of the states, for WMs reporting all of them from one
+/// connection.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Change {
+    Workspace(WorkspaceChange),
+    Window(WindowChange),
+    Compositor(CompositorChange),
+}
+
+impl From<WorkspaceChange> for Change {
+    fn from(change: WorkspaceChange) -> Change {
+        Change::Workspace(change)
+    }
+}
+
+impl From<WindowChange> for Change {
+    fn from(change: WindowChange) -> Change {
+        Change::Window(change)
+    }
+}
+
+impl From<CompositorChange> for Change {
+    fn from(change: CompositorChange) -> Change {
+        Change::Compositor(change)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn destroyed_workspaces_lose_their_info() {
+        let mut state = WorkspaceState::new();
+        let info = WorkspaceInfo {
+            name: Some("1: web".to_string()),
+            ..Default::default()
+        };
+        state.update(WorkspaceChange::Create(1));
+        assert!(state.update(WorkspaceChange::Describe(1, info.clone())));
+        assert!(!state.update(WorkspaceChange::Describe(1, info)));
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"active_workspace":0,"workspaces":[1],"info":{"1":{"name":"1: web"}}}"#
+        );
+        state.update(WorkspaceChange::Destroy(1));
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"active_workspace":0,"workspaces":[]}"#
+        );
+    }
+
+    #[test]
+    fn window_state_only_reports_real_changes() {
+        let mut state = WindowState::new();
+        assert!(state.update(WindowChange::Focus {
+            class: "kitty".to_string(),
+            title: "~".to_string(),
+        }));
+        assert!(!state.update(WindowChange::Focus {
+            class: "kitty".to_string(),
+            title: "~".to_string(),
+        }));
+        assert!(state.update(WindowChange::Fullscreen(true)));
+        assert!(!state.update(WindowChange::Fullscreen(true)));
+        assert!(state.update(WindowChange::Workspace("2".to_string())));
+    }
+
+    #[test]
+    fn default_submap_is_empty() {
+        let mut state = CompositorState::new();
+        assert!(state.update(CompositorChange::Submap("resize".to_string())));
+        assert!(state.update(CompositorChange::Submap("default".to_string())));
+        assert!(!state.update(CompositorChange::Submap(String::new())));
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"submap":"","layout":"","special":"","fullscreen":false,"floating":false}"#
+        );
+    }
+}
@@ -1,3 +1,791 @@
 pub mod hyprland;
+pub mod i3;
 pub mod internal;
+pub mod niri;
+pub mod persistent;
+pub mod scroll;
 pub mod sway;
+pub mod wayland;
+
+use std::convert::Infallible;
+use std::env;
+use std::io::Write;
+use std::str::FromStr;
+use std::thread;
+use std::time::Duration;
+
+use anyhow::bail;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::config::Config;
+use internal::{Change, CompositorState, WindowState, WorkspaceState};
+use persistent::{Entry, Persistent};
+
+/// A source of workspace events, e.g. a compositor's IPC socket.
+///
+/// Backends only translate their native events into
+/// [`WorkspaceChange`](internal::WorkspaceChange)s, or [`Change`]s when they
+/// report the focused window and compositor state as well, [`listen`] takes
+/// care of the state and output.
+pub trait WindowManager {
+    type Change: Into<Change>;
+
+    /// Changes describing the state when the listener starts.
+    fn snapshot(&mut self) -> anyhow::Result<Vec<Self::Change>>;
+
+    /// Blocks until the next event and returns the changes it caused.
+    ///
+    /// Events that aren't relevant produce no changes, `None` means the
+    /// event stream has ended.
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<Self::Change>>>;
+}
+
+/// The topics a listener follows, WMs only ask for and subscribe to what
+/// they need.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Subscriptions {
+    pub workspaces: bool,
+    pub window: bool,
+    pub compositor: bool,
+}
+
+impl Subscriptions {
+    pub const WORKSPACES: Subscriptions = Subscriptions {
+        workspaces: true,
+        window: false,
+        compositor: false,
+    };
+}
+
+/// Where a listener writes each topic, only the ones with a writer are
+/// followed.
+#[derive(Default)]
+pub struct Outputs<'a> {
+    pub workspaces: Option<&'a mut dyn Write>,
+    pub window: Option<&'a mut dyn Write>,
+    pub compositor: Option<&'a mut dyn Write>,
+}
+
+impl Outputs<'_> {
+    pub fn subscriptions(&self) -> Subscriptions {
+        Subscriptions {
+            workspaces: self.workspaces.is_some(),
+            window: self.window.is_some(),
+            compositor: self.compositor.is_some(),
+        }
+    }
+}
+
+/// A workspace given on the command line.
+///
+/// Numbers are the ids printed by the listener, anything else is a name.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Target {
+    Id(i64),
+    Name(String),
+}
+
+impl FromStr for Target {
+    type Err = Infallible;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Ok(s.parse()
+            .map_or_else(|_| Target::Name(s.to_string()), Target::Id))
+    }
+}
+
+/// A change to the workspaces requested from the compositor.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Action {
+    /// Switches to the workspace, creating it if needed.
+    Focus(Target),
+    /// Moves the focused window to the workspace.
+    MoveWindow(Target),
+    /// Creates the workspace, most compositors also switch to it.
+    Create(Target),
+    /// Gives the workspace a new name.
+    Rename(Target, String),
+}
+
+impl Action {
+    /// The workspace the action applies to.
+    pub fn target(&self) -> &Target {
+        match self {
+            Action::Focus(ws) | Action::MoveWindow(ws) | Action::Create(ws) => ws,
+            Action::Rename(ws, _) => ws,
+        }
+    }
+}
+
+/// Compositors that can be recognised from their environment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Compositor {
+    Hyprland,
+    Sway,
+    Niri,
+    I3,
+    /// Compositors only exposing workspaces through Wayland protocols.
+    Wayland,
+}
+
+/// Environment variables that identify a compositor by their presence.
+const SOCKET_VARS: [(&str, Compositor); 4] = [
+    ("HYPRLAND_INSTANCE_SIGNATURE", Compositor::Hyprland),
+    ("SWAYSOCK", Compositor::Sway),
+    ("NIRI_SOCKET", Compositor::Niri),
+    ("I3SOCK", Compositor::I3),
+];
+
+/// Detects the running compositor from the environment.
+pub fn detect() -> anyhow::Result<Compositor> {
+    detect_from(|key| env::var(key).ok())
+}
+
+fn detect_from(var: impl Fn(&str) -> Option<String>) -> anyhow::Result<Compositor> {
+    for (key, compositor) in SOCKET_VARS {
+        if var(key).is_some_and(|v| !v.is_empty()) {
+            return Ok(compositor);
+        }
+    }
+    // A colon separated list, e.g. `sway:wlroots`.
+    let desktops = var("XDG_CURRENT_DESKTOP").unwrap_or_default();
+    for desktop in desktops.split(':') {
+        match desktop.to_lowercase().as_str() {
+            "hyprland" => return Ok(Compositor::Hyprland),
+            "sway" => return Ok(Compositor::Sway),
+            "niri" => return Ok(Compositor::Niri),
+            "i3" => return Ok(Compositor::I3),
+            "river" | "labwc" | "wayfire" => return Ok(Compositor::Wayland),
+            _ => continue,
+        }
+    }
+    let checked: Vec<_> = SOCKET_VARS.iter().map(|(key, _)| *key).collect();
+    bail!(
+        "Unable to detect the window manager, checked {} and XDG_CURRENT_DESKTOP (={:?}). \
+        Pass the window manager explicitly or set `wm` in the config, see -h.",
+        checked.join(", "),
+        desktops
+    )
+}
+
+/// Everything the listeners report, kept up to date from one connection.
+#[derive(Debug, Default)]
+struct State {
+    workspaces: WorkspaceState,
+    window: WindowState,
+    compositor: CompositorState,
+}
+
+impl State {
+    fn update(&mut self, change: Change) {
+        match change {
+            Change::Workspace(change) => self.workspaces.update(change),
+            Change::Window(change) => self.window.update(change),
+            Change::Compositor(change) => self.compositor.update(change),
+        };
+    }
+}
+
+/// The state as it is right now.
+fn snapshot(wm: &mut impl WindowManager) -> anyhow::Result<State> {
+    let mut state = State::default();
+    for change in wm.snapshot()? {
+        state.update(change.into());
+    }
+    Ok(state)
+}
+
+/// The workspaces as they are right now.
+pub fn current_state(wm: &mut impl WindowManager) -> anyhow::Result<WorkspaceState> {
+    Ok(snapshot(wm)?.workspaces)
+}
+
+/// How the listener reports the workspaces.
+#[derive(Debug, Clone, Default)]
+pub struct ListenOptions {
+    /// Workspaces listed in `entries` even when they don't exist.
+    pub persistent: Vec<Persistent>,
+    /// Takes the persistent workspaces from the config instead, applied with
+    /// the next change after it's reloaded.
+    pub config: Option<watch::Receiver<Config>>,
+}
+
+/// A line printed by the workspace listener.
+#[derive(Serialize)]
+struct Report<'a> {
+    #[serde(flatten)]
+    state: &'a WorkspaceState,
+    /// Only reported when persistent workspaces are configured.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    entries: Vec<Entry>,
+    /// Only reported while the WM is unreachable.
+    #[serde(skip_serializing_if = "is_true")]
+    connected: bool,
+}
+
+fn is_true(b: &bool) -> bool {
+    *b
+}
+
+/// A line printed by the window and compositor listeners.
+#[derive(Serialize)]
+struct Connected<'a, S> {
+    #[serde(flatten)]
+    state: &'a S,
+    /// Only reported while the WM is unreachable.
+    #[serde(skip_serializing_if = "is_true")]
+    connected: bool,
+}
+
+impl ListenOptions {
+    fn report(&self, state: &WorkspaceState, connected: bool) -> serde_json::Result<String> {
+        let config = self.config.as_ref().map(|config| config.borrow());
+        let persistent = match &config {
+            Some(config) => &config.workspaces.persistent,
+            None => &self.persistent,
+        };
+        let entries = match persistent.is_empty() {
+            true => vec![],
+            false => persistent::entries(state, persistent),
+        };
+        serde_json::to_string(&Report {
+            state,
+            entries,
+            connected,
+        })
+    }
+}
+
+/// Writes each followed topic's state, unless it's unchanged.
+struct Writer<'a, 'o> {
+    options: &'a ListenOptions,
+    outputs: Outputs<'o>,
+    /// The last line of the workspaces, window and compositor.
+    last: [Option<String>; 3],
+}
+
+impl<'a, 'o> Writer<'a, 'o> {
+    fn new(options: &'a ListenOptions, outputs: Outputs<'o>) -> Writer<'a, 'o> {
+        Writer {
+            options,
+            outputs,
+            last: Default::default(),
+        }
+    }
+
+    fn write(&mut self, state: &State, connected: bool) -> anyhow::Result<()> {
+        let [workspaces, window, compositor] = &mut self.last;
+        write_line(&mut self.outputs.workspaces, workspaces, || {
+            self.options.report(&state.workspaces, connected)
+        })?;
+        write_line(&mut self.outputs.window, window, || {
+            serde_json::to_string(&Connected {
+                state: &state.window,
+                connected,
+            })
+        })?;
+        write_line(&mut self.outputs.compositor, compositor, || {
+            serde_json::to_string(&Connected {
+                state: &state.compositor,
+                connected,
+            })
+        })
+    }
+}
+
+/// Writes `line` to `out` when there's one and it differs from `last`.
+fn write_line(
+    out: &mut Option<&mut dyn Write>,
+    last: &mut Option<String>,
+    line: impl FnOnce() -> serde_json::Result<String>,
+) -> anyhow::Result<()> {
+    let Some(out) = out else {
+        return Ok(());
+    };
+    let line = line()?;
+    if last.as_ref() == Some(&line) {
+        return Ok(());
+    }
+    writeln!(out, "{line}")?;
+    out.flush()?;
+    *last = Some(line);
+    Ok(())
+}
+
+/// Writes the workspace state as a JSON line every time it changes.
+pub fn listen(
+    wm: &mut impl WindowManager,
+    options: &ListenOptions,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let mut state = snapshot(wm)?;
+    let outputs = Outputs {
+        workspaces: Some(out),
+        ..Default::default()
+    };
+    follow(wm, &mut Writer::new(options, outputs), &mut state)
+}
+
+/// Writes `state`, then keeps it up to date until the event stream ends.
+fn follow(
+    wm: &mut impl WindowManager,
+    writer: &mut Writer,
+    state: &mut State,
+) -> anyhow::Result<()> {
+    writer.write(state, true)?;
+    while let Some(changes) = wm.next_event()? {
+        for change in changes {
+            state.update(change.into());
+        }
+        writer.write(state, true)?;
+    }
+    Ok(())
+}
+
+/// Delays between reconnection attempts, doubling from `min` up to `max`.
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub min: Duration,
+    pub max: Duration,
+}
+
+impl Default for Backoff {
+    fn default() -> Self {
+        Backoff {
+            min: Duration::from_millis(250),
+            max: Duration::from_secs(30),
+        }
+    }
+}
+
+/// Like [`listen`], but reconnects whenever the WM goes away, e.g. when it
+/// restarts. Only returns once `out` can't be written to.
+pub fn listen_reconnecting<W: WindowManager>(
+    connect: impl FnMut() -> anyhow::Result<W>,
+    options: &ListenOptions,
+    backoff: Backoff,
+    out: &mut impl Write,
+) -> anyhow::Result<()> {
+    let outputs = Outputs {
+        workspaces: Some(out),
+        ..Default::default()
+    };
+    follow_reconnecting(connect, options, backoff, outputs)
+}
+
+/// Writes every topic in `outputs` each time it changes, reconnecting
+/// whenever the WM goes away. Only returns once an output can't be written to.
+///
+/// The last known states are written with `"connected":false` while the WM
+/// is unreachable, and re-read from scratch after reconnecting.
+pub fn follow_reconnecting<W: WindowManager>(
+    mut connect: impl FnMut() -> anyhow::Result<W>,
+    options: &ListenOptions,
+    backoff: Backoff,
+    outputs: Outputs,
+) -> anyhow::Result<()> {
+    let mut writer = Writer::new(options, outputs);
+    let mut state = State::default();
+    let mut delay = backoff.min;
+    // Start out connected, failing to connect at all reports it as well.
+    let mut connected = true;
+    loop {
+        let seeded = connect().and_then(|mut wm| {
+            let state = snapshot(&mut wm)?;
+            Ok((wm, state))
+        });
+        match seeded {
+            Ok((mut wm, seeded)) => {
+                state = seeded;
+                connected = true;
+                delay = backoff.min;
+                match follow(&mut wm, &mut writer, &mut state) {
+                    Ok(()) => eprintln!("The event stream ended, reconnecting."),
+                    Err(e) => eprintln!("Lost the connection ({e:#}), reconnecting."),
+                }
+            }
+            Err(e) => eprintln!("Couldn't connect ({e:#}), retrying in {delay:?}."),
+        }
+        if connected {
+            writer.write(&state, false)?;
+            connected = false;
+        }
+        thread::sleep(delay);
+        delay = (delay * 2).min(backoff.max);
+    }
+}
+
+impl Compositor {
+    /// Whether the focused window and compositor state can be followed.
+    pub fn has_window_listeners(self) -> bool {
+        !matches!(self, Compositor::Niri | Compositor::Wayland)
+    }
+
+    /// Writes every topic in `outputs` each time it changes, all from one
+    /// connection, reconnecting whenever the compositor goes away.
+    pub fn follow(self, options: &ListenOptions, outputs: Outputs) -> anyhow::Result<()> {
+        let subs = outputs.subscriptions();
+        if (subs.window || subs.compositor) && !self.has_window_listeners() {
+            bail!("The window and compositor listeners aren't available for {self:?} yet.")
+        }
+        let backoff = Backoff::default();
+        match self {
+            Compositor::Hyprland => follow_reconnecting(
+                || hyprland::Hyprland::connect(subs),
+                options,
+                backoff,
+                outputs,
+            ),
+            Compositor::Sway => {
+                follow_reconnecting(|| sway::Sway::connect(subs), options, backoff, outputs)
+            }
+            Compositor::Niri => follow_reconnecting(niri::Niri::connect, options, backoff, outputs),
+            Compositor::I3 => {
+                follow_reconnecting(|| i3::I3::connect(subs), options, backoff, outputs)
+            }
+            Compositor::Wayland => {
+                follow_reconnecting(wayland::Wayland::connect, options, backoff, outputs)
+            }
+        }
+    }
+
+    /// Writes the workspaces to `out` every time they change, reconnecting
+    /// whenever the compositor goes away.
+    pub fn listen(self, options: &ListenOptions, out: &mut impl Write) -> anyhow::Result<()> {
+        let outputs = Outputs {
+            workspaces: Some(out),
+            ..Default::default()
+        };
+        self.follow(options, outputs)
+    }
+
+    /// The workspaces as they are right now.
+    pub fn current_state(self) -> anyhow::Result<WorkspaceState> {
+        let subs = Subscriptions::WORKSPACES;
+        match self {
+            Compositor::Hyprland => current_state(&mut hyprland::Hyprland::connect(subs)?),
+            Compositor::Sway => current_state(&mut sway::Sway::connect(subs)?),
+            Compositor::Niri => current_state(&mut niri::Niri::connect()?),
+            Compositor::I3 => current_state(&mut i3::I3::connect(subs)?),
+            Compositor::Wayland => current_state(&mut wayland::Wayland::connect()?),
+        }
+    }
+
+    pub fn run(self, action: &Action) -> anyhow::Result<()> {
+        match self {
+            Compositor::Hyprland => hyprland::run(action),
+            Compositor::Sway => sway::run(action),
+            Compositor::Niri => niri::run(action),
+            Compositor::I3 => i3::run(action),
+            Compositor::Wayland => wayland::run(action),
+        }
+    }
+
+    /// Writes the focused window to `out` every time it changes, reconnecting
+    /// whenever the compositor goes away.
+    pub fn listen_window(self, out: &mut impl Write) -> anyhow::Result<()> {
+        let outputs = Outputs {
+            window: Some(out),
+            ..Default::default()
+        };
+        self.follow(&ListenOptions::default(), outputs)
+    }
+
+    /// Writes the submap, keyboard layout, etc. to `out` every time they
+    /// change, reconnecting whenever the compositor goes away.
+    pub fn listen_compositor(self, out: &mut impl Write) -> anyhow::Result<()> {
+        let outputs = Outputs {
+            compositor: Some(out),
+            ..Default::default()
+        };
+        self.follow(&ListenOptions::default(), outputs)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::VecDeque;
+
+    use super::internal::WorkspaceChange;
+    use super::*;
+
+    /// Replays a fixed list of events.
+    struct FakeWm<C = WorkspaceChange> {
+        snapshot: Vec<C>,
+        events: VecDeque<Vec<C>>,
+    }
+
+    impl<C: Into<Change>> WindowManager for FakeWm<C> {
+        type Change = C;
+
+        fn snapshot(&mut self) -> anyhow::Result<Vec<C>> {
+            Ok(std::mem::take(&mut self.snapshot))
+        }
+
+        fn next_event(&mut self) -> anyhow::Result<Option<Vec<C>>> {
+            Ok(self.events.pop_front())
+        }
+    }
+
+    fn env<'a>(vars: &'a [(&'a str, &'a str)]) -> impl Fn(&str) -> Option<String> + 'a {
+        |key| {
+            vars.iter()
+                .find(|(k, _)| *k == key)
+                .map(|(_, v)| v.to_string())
+        }
+    }
+
+    #[test]
+    fn targets_are_ids_or_names() {
+        assert_eq!("3".parse(), Ok(Target::Id(3)));
+        assert_eq!("-98".parse(), Ok(Target::Id(-98)));
+        assert_eq!("3: web".parse(), Ok(Target::Name("3: web".into())));
+    }
+
+    #[test]
+    fn compositor_is_detected_from_its_socket() {
+        let detected = detect_from(env(&[("SWAYSOCK", "/run/user/1000/sway-ipc.sock")]));
+        assert_eq!(detected.unwrap(), Compositor::Sway);
+        let detected = detect_from(env(&[
+            ("HYPRLAND_INSTANCE_SIGNATURE", "abc"),
+            ("XDG_CURRENT_DESKTOP", "sway"),
+        ]));
+        assert_eq!(detected.unwrap(), Compositor::Hyprland);
+    }
+
+    #[test]
+    fn compositor_is_detected_from_the_current_desktop() {
+        let detected = detect_from(env(&[("XDG_CURRENT_DESKTOP", "niri:GNOME")]));
+        assert_eq!(detected.unwrap(), Compositor::Niri);
+        let detected = detect_from(env(&[("SWAYSOCK", ""), ("XDG_CURRENT_DESKTOP", "i3")]));
+        assert_eq!(detected.unwrap(), Compositor::I3);
+        let detected = detect_from(env(&[("XDG_CURRENT_DESKTOP", "river")]));
+        assert_eq!(detected.unwrap(), Compositor::Wayland);
+    }
+
+    #[test]
+    fn detection_failure_lists_what_was_checked() {
+        let err = detect_from(env(&[("XDG_CURRENT_DESKTOP", "GNOME")]))
+            .unwrap_err()
+            .to_string();
+        for key in [
+            "HYPRLAND_INSTANCE_SIGNATURE",
+            "SWAYSOCK",
+            "NIRI_SOCKET",
+            "I3SOCK",
+        ] {
+            assert!(err.contains(key), "{err}");
+        }
+        assert!(err.contains(r#"XDG_CURRENT_DESKTOP (="GNOME")"#), "{err}");
+    }
+
+    fn run(wm: &mut FakeWm) -> Vec<String> {
+        let mut out = Vec::new();
+        listen(wm, &ListenOptions::default(), &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(String::from)
+            .collect()
+    }
+
+    #[test]
+    fn snapshot_is_printed_first() {
+        let mut wm = FakeWm {
+            snapshot: vec![WorkspaceChange::Create(1), WorkspaceChange::Focus(2)],
+            events: VecDeque::new(),
+        };
+        assert_eq!(
+            run(&mut wm),
+            vec![r#"{"active_workspace":2,"workspaces":[1,2]}"#]
+        );
+    }
+
+    #[test]
+    fn persistent_workspaces_add_entries() {
+        let mut wm = FakeWm {
+            snapshot: vec![WorkspaceChange::Focus(2)],
+            events: VecDeque::new(),
+        };
+        let options = ListenOptions {
+            persistent: vec!["1-2".parse().unwrap()],
+            ..Default::default()
+        };
+        let mut out = Vec::new();
+        listen(&mut wm, &options, &mut out).unwrap();
+        assert_eq!(
+            String::from_utf8(out).unwrap(),
+            r#"{"active_workspace":2,"workspaces":[2],"entries":[{"id":1,"status":"empty"},{"id":2,"status":"focused"}]}"#
+                .to_owned()
+                + "\n"
+        );
+    }
+
+    #[test]
+    fn reloaded_persistent_workspaces_are_reported() {
+        let (tx, rx) = watch::channel(Config::default());
+        let options = ListenOptions {
+            persistent: vec!["1-2".parse().unwrap()],
+            config: Some(rx),
+        };
+        let mut state = WorkspaceState::new();
+        state.update(WorkspaceChange::Focus(1));
+        let report = options.report(&state, true).unwrap();
+        assert_eq!(report, r#"{"active_workspace":1,"workspaces":[1]}"#);
+        tx.send_modify(|config| config.workspaces.persistent = vec!["2-2".parse().unwrap()]);
+        let report = options.report(&state, true).unwrap();
+        assert!(
+            report
+                .ends_with(r#""entries":[{"id":1,"status":"focused"},{"id":2,"status":"empty"}]}"#),
+            "{report}"
+        );
+    }
+
+    /// Collects output until `limit` lines are written, then fails like a
+    /// closed pipe.
+    struct Lines {
+        out: String,
+        limit: usize,
+    }
+
+    impl Write for Lines {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.out.lines().count() == self.limit {
+                return Err(std::io::ErrorKind::BrokenPipe.into());
+            }
+            self.out.push_str(&String::from_utf8_lossy(buf));
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn listener_reconnects_and_reseeds() {
+        let mut attempts = 0;
+        let connect = || {
+            attempts += 1;
+            match attempts {
+                1 => Ok(FakeWm {
+                    snapshot: vec![WorkspaceChange::Focus(1)],
+                    events: VecDeque::from([vec![WorkspaceChange::Focus(2)]]),
+                }),
+                2 => bail!("Connection refused"),
+                _ => Ok(FakeWm {
+                    snapshot: vec![WorkspaceChange::Focus(3)],
+                    events: VecDeque::new(),
+                }),
+            }
+        };
+        let backoff = Backoff {
+            min: Duration::ZERO,
+            max: Duration::ZERO,
+        };
+        let mut out = Lines {
+            out: String::new(),
+            limit: 5,
+        };
+        let result = listen_reconnecting(connect, &ListenOptions::default(), backoff, &mut out);
+        assert!(result.is_err());
+        assert_eq!(
+            out.out.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"active_workspace":1,"workspaces":[1]}"#,
+                r#"{"active_workspace":2,"workspaces":[1,2]}"#,
+                // The failed attempt doesn't repeat the marker.
+                r#"{"active_workspace":2,"workspaces":[1,2],"connected":false}"#,
+                r#"{"active_workspace":3,"workspaces":[3]}"#,
+                r#"{"active_workspace":3,"workspaces":[3],"connected":false}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn window_and_compositor_listeners_reconnect() {
+        use internal::{CompositorChange, WindowChange};
+
+        let mut attempts = 0;
+        let connect = || {
+            attempts += 1;
+            match attempts {
+                1 => Ok(FakeWm {
+                    snapshot: vec![Change::Window(WindowChange::Focus {
+                        class: "kitty".into(),
+                        title: "~".into(),
+                    })],
+                    events: VecDeque::from([vec![Change::Compositor(CompositorChange::Submap(
+                        "resize".into(),
+                    ))]]),
+                }),
+                2 => bail!("Connection refused"),
+                _ => Ok(FakeWm {
+                    snapshot: vec![],
+                    events: VecDeque::new(),
+                }),
+            }
+        };
+        let backoff = Backoff {
+            min: Duration::ZERO,
+            max: Duration::ZERO,
+        };
+        let mut window = Vec::new();
+        let mut compositor = Lines {
+            out: String::new(),
+            limit: 4,
+        };
+        let outputs = Outputs {
+            window: Some(&mut window),
+            compositor: Some(&mut compositor),
+            ..Default::default()
+        };
+        let result = follow_reconnecting(connect, &ListenOptions::default(), backoff, outputs);
+        assert!(result.is_err());
+        assert_eq!(
+            String::from_utf8(window)
+                .unwrap()
+                .lines()
+                .collect::<Vec<_>>(),
+            vec![
+                r#"{"class":"kitty","title":"~","workspace":"","fullscreen":false}"#,
+                r#"{"class":"kitty","title":"~","workspace":"","fullscreen":false,"connected":false}"#,
+                r#"{"class":"","title":"","workspace":"","fullscreen":false}"#,
+                r#"{"class":"","title":"","workspace":"","fullscreen":false,"connected":false}"#,
+            ]
+        );
+        assert_eq!(
+            compositor.out.lines().collect::<Vec<_>>(),
+            vec![
+                r#"{"submap":"","layout":"","special":"","fullscreen":false,"floating":false}"#,
+                r#"{"submap":"resize","layout":"","special":"","fullscreen":false,"floating":false}"#,
+                r#"{"submap":"resize","layout":"","special":"","fullscreen":false,"floating":false,"connected":false}"#,
+                r#"{"submap":"","layout":"","special":"","fullscreen":false,"floating":false}"#,
+            ]
+        );
+    }
+
+    #[test]
+    fn unchanged_states_are_not_printed() {
+        let mut wm = FakeWm {
+            snapshot: vec![WorkspaceChange::Focus(1)],
+            events: VecDeque::from([
+                vec![WorkspaceChange::Focus(1)],
+                vec![],
+                vec![WorkspaceChange::Create(3), WorkspaceChange::Focus(3)],
+                vec![WorkspaceChange::Create(3)],
+                vec![WorkspaceChange::Destroy(1)],
+            ]),
+        };
+        assert_eq!(
+            run(&mut wm),
+            vec![
+                r#"{"active_workspace":1,"workspaces":[1]}"#,
+                r#"{"active_workspace":3,"workspaces":[1,3]}"#,
+                r#"{"active_workspace":3,"workspaces":[3]}"#,
+            ]
+        );
+    }
+}
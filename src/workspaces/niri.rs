@@ -0,0 +1,311 @@
+use std::collections::BTreeMap;
+use std::env;
+use std::io::{BufRead, BufReader, Write};
+use std::os::unix::net::UnixStream;
+
+use anyhow::{bail, Context};
+use serde::Deserialize;
+use serde_json::json;
+
+use super::internal::{WorkspaceChange, WorkspaceInfo};
+use super::{Action, Target, WindowManager};
+
+/// niri's IPC event stream, see `niri msg --help` and the `niri-ipc` crate.
+///
+/// niri ids are unique across outputs and used as the workspace id, the
+/// per-output `idx` is reported as the workspace's index.
+pub struct Niri {
+    events: BufReader<UnixStream>,
+    workspaces: BTreeMap<u64, NiriWorkspace>,
+    /// Window id to the id of the workspace it's on.
+    windows: BTreeMap<u64, Option<u64>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct NiriWorkspace {
+    id: u64,
+    idx: u32,
+    name: Option<String>,
+    output: Option<String>,
+    is_focused: bool,
+}
+
+#[derive(Debug, Deserialize)]
+struct NiriWindow {
+    id: u64,
+    workspace_id: Option<u64>,
+}
+
+/// The subset of niri's `Event` enum that affects workspaces.
+#[derive(Debug, Deserialize)]
+enum Event {
+    WorkspacesChanged { workspaces: Vec<NiriWorkspace> },
+    WorkspaceActivated { id: u64, focused: bool },
+    WindowsChanged { windows: Vec<NiriWindow> },
+    WindowOpenedOrChanged { window: NiriWindow },
+    WindowClosed { id: u64 },
+}
+
+/// Connects to niri's IPC socket.
+fn socket() -> anyhow::Result<UnixStream> {
+    let path = env::var("NIRI_SOCKET").context("NIRI_SOCKET isn't set, is niri running?")?;
+    Ok(UnixStream::connect(path)?)
+}
+
+impl Niri {
+    pub fn connect() -> anyhow::Result<Niri> {
+        Niri::from_stream(socket()?)
+    }
+
+    /// Requests the event stream on an open connection to niri's socket.
+    pub fn from_stream(mut stream: UnixStream) -> anyhow::Result<Niri> {
+        stream.write_all(b"\"EventStream\"\n")?;
+        let mut events = BufReader::new(stream);
+        let mut reply = String::new();
+        events.read_line(&mut reply)?;
+        if reply.trim() != r#"{"Ok":"Handled"}"# {
+            bail!("niri refused the event stream: {}", reply.trim());
+        }
+        Ok(Niri {
+            events,
+            workspaces: BTreeMap::new(),
+            windows: BTreeMap::new(),
+        })
+    }
+
+    /// Reads the next event, `None` once niri closes the socket.
+    fn read_event(&mut self) -> anyhow::Result<Option<Option<Event>>> {
+        let mut line = String::new();
+        if self.events.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        // Events we don't care about don't deserialize.
+        Ok(Some(serde_json::from_str(&line).ok()))
+    }
+
+    /// Describes every known workspace, including its window count.
+    fn describe_all(&self) -> Vec<WorkspaceChange> {
+        self.workspaces
+            .values()
+            .map(|ws| {
+                let windows = self.windows.values().filter(|&&w| w == Some(ws.id)).count();
+                WorkspaceChange::Describe(
+                    ws.id as i64,
+                    WorkspaceInfo {
+                        name: ws.name.clone(),
+                        output: ws.output.clone(),
+                        index: Some(ws.idx),
+                        windows: Some(windows as u32),
+                    },
+                )
+            })
+            .collect()
+    }
+
+    fn apply(&mut self, event: Event) -> Vec<WorkspaceChange> {
+        match event {
+            Event::WorkspacesChanged { workspaces } => {
+                let mut changes = vec![WorkspaceChange::Reset];
+                for ws in &workspaces {
+                    changes.push(WorkspaceChange::Create(ws.id as i64));
+                }
+                if let Some(ws) = workspaces.iter().find(|ws| ws.is_focused) {
+                    changes.push(WorkspaceChange::Focus(ws.id as i64));
+                }
+                self.workspaces = workspaces.into_iter().map(|ws| (ws.id, ws)).collect();
+                changes.extend(self.describe_all());
+                return changes;
+            }
+            Event::WorkspaceActivated { id, focused } => {
+                // Activations without focus only change what's shown on an output.
+                if !focused {
+                    return vec![];
+                }
+                return vec![WorkspaceChange::Focus(id as i64)];
+            }
+            Event::WindowsChanged { windows } => {
+                self.windows = windows
+                    .into_iter()
+                    .map(|w| (w.id, w.workspace_id))
+                    .collect();
+            }
+            Event::WindowOpenedOrChanged { window } => {
+                self.windows.insert(window.id, window.workspace_id);
+            }
+            Event::WindowClosed { id } => {
+                self.windows.remove(&id);
+            }
+        }
+        self.describe_all()
+    }
+}
+
+impl WindowManager for Niri {
+    type Change = WorkspaceChange;
+
+    /// niri starts the event stream with the full state, the snapshot
+    /// consumes events until the workspaces are known.
+    fn snapshot(&mut self) -> anyhow::Result<Vec<WorkspaceChange>> {
+        let mut changes = vec![];
+        while let Some(event) = self.read_event()? {
+            let Some(event) = event else {
+                continue;
+            };
+            let initial = matches!(event, Event::WorkspacesChanged { .. });
+            changes.extend(self.apply(event));
+            if initial {
+                break;
+            }
+        }
+        Ok(changes)
+    }
+
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<WorkspaceChange>>> {
+        Ok(self
+            .read_event()?
+            .map(|event| event.map_or(vec![], |e| self.apply(e))))
+    }
+}
+
+/// The `Request` performing `action`, see `niri_ipc::Action`.
+fn request(action: &Action) -> anyhow::Result<serde_json::Value> {
+    let reference = match action.target() {
+        Target::Id(id) => json!({ "Id": id }),
+        Target::Name(name) => json!({ "Name": name }),
+    };
+    let action = match action {
+        Action::Focus(_) => json!({ "FocusWorkspace": { "reference": reference } }),
+        Action::MoveWindow(_) => json!({ "MoveWindowToWorkspace": {
+            "window_id": null,
+            "reference": reference,
+            "focus": true,
+        } }),
+        Action::Create(_) => bail!("niri creates workspaces as they're needed, focus one instead."),
+        Action::Rename(_, name) => json!({ "SetWorkspaceName": {
+            "name": name,
+            "workspace": reference,
+        } }),
+    };
+    Ok(json!({ "Action": action }))
+}
+
+/// Sends a single request over `stream` and checks niri handled it.
+fn send(mut stream: UnixStream, request: &serde_json::Value) -> anyhow::Result<()> {
+    writeln!(stream, "{request}")?;
+    let mut reply = String::new();
+    BufReader::new(stream).read_line(&mut reply)?;
+    if reply.trim() != r#"{"Ok":"Handled"}"# {
+        bail!("niri refused the request: {}", reply.trim());
+    }
+    Ok(())
+}
+
+pub fn run(action: &Action) -> anyhow::Result<()> {
+    send(socket()?, &request(action)?)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::thread;
+
+    use super::*;
+
+    const EVENT_STREAM: &str = include_str!("fixtures/niri-event-stream.jsonl");
+
+    /// Connects to a fake niri that replays `lines` after the request.
+    fn replay(lines: &'static str) -> Niri {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        thread::spawn(move || {
+            let mut request = [0_u8; 14];
+            server.read_exact(&mut request).unwrap();
+            assert_eq!(&request, b"\"EventStream\"\n");
+            server.write_all(lines.as_bytes()).unwrap();
+        });
+        Niri::from_stream(client).unwrap()
+    }
+
+    fn run(wm: &mut Niri) -> Vec<serde_json::Value> {
+        let mut out = Vec::new();
+        super::super::listen(wm, &Default::default(), &mut out).unwrap();
+        String::from_utf8(out)
+            .unwrap()
+            .lines()
+            .map(|l| serde_json::from_str(l).unwrap())
+            .collect()
+    }
+
+    #[test]
+    fn refused_event_stream_is_an_error() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        server
+            .write_all(b"{\"Err\":\"unknown request\"}\n")
+            .unwrap();
+        assert!(Niri::from_stream(client).is_err());
+    }
+
+    #[test]
+    fn actions_are_requests() {
+        let (client, mut server) = UnixStream::pair().unwrap();
+        let niri = thread::spawn(move || {
+            let mut request = String::new();
+            BufReader::new(server.try_clone().unwrap())
+                .read_line(&mut request)
+                .unwrap();
+            server.write_all(b"{\"Ok\":\"Handled\"}\n").unwrap();
+            request
+        });
+        let action = Action::Rename(Target::Id(3), "web".into());
+        send(client, &request(&action).unwrap()).unwrap();
+        assert_eq!(
+            niri.join().unwrap(),
+            r#"{"Action":{"SetWorkspaceName":{"name":"web","workspace":{"Id":3}}}}"#.to_owned()
+                + "\n"
+        );
+        let action = Action::MoveWindow(Target::Name("chat".into()));
+        assert_eq!(
+            request(&action).unwrap().to_string(),
+            r#"{"Action":{"MoveWindowToWorkspace":{"focus":true,"reference":{"Name":"chat"},"window_id":null}}}"#
+        );
+        assert!(request(&Action::Create(Target::Id(4))).is_err());
+    }
+
+    #[test]
+    fn event_stream_is_replayed() {
+        let states = run(&mut replay(EVENT_STREAM));
+        let expected = [
+            // Initial workspaces
+            r#"{"active_workspace":1,"workspaces":[1,2,3],"info":{
+                "1":{"name":"web","output":"DP-1","index":1,"windows":0},
+                "2":{"output":"DP-1","index":2,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":0}}}"#,
+            // WindowsChanged
+            r#"{"active_workspace":1,"workspaces":[1,2,3],"info":{
+                "1":{"name":"web","output":"DP-1","index":1,"windows":1},
+                "2":{"output":"DP-1","index":2,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":1}}}"#,
+            // WorkspaceActivated, focused
+            r#"{"active_workspace":3,"workspaces":[1,2,3],"info":{
+                "1":{"name":"web","output":"DP-1","index":1,"windows":1},
+                "2":{"output":"DP-1","index":2,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":1}}}"#,
+            // WindowOpenedOrChanged, a new window
+            r#"{"active_workspace":3,"workspaces":[1,2,3],"info":{
+                "1":{"name":"web","output":"DP-1","index":1,"windows":1},
+                "2":{"output":"DP-1","index":2,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":2}}}"#,
+            // WindowClosed
+            r#"{"active_workspace":3,"workspaces":[1,2,3],"info":{
+                "1":{"name":"web","output":"DP-1","index":1,"windows":0},
+                "2":{"output":"DP-1","index":2,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":2}}}"#,
+            // WorkspacesChanged, the emptied workspace is removed
+            r#"{"active_workspace":3,"workspaces":[2,3,4],"info":{
+                "2":{"output":"DP-1","index":1,"windows":0},
+                "3":{"output":"eDP-1","index":1,"windows":2},
+                "4":{"output":"eDP-1","index":2,"windows":0}}}"#,
+        ]
+        .map(|s| serde_json::from_str::<serde_json::Value>(s).unwrap());
+        assert_eq!(states, expected);
+    }
+}
@@ -0,0 +1,211 @@
+//! Workspaces that are always part of the output, whether they exist or not.
+
+use std::collections::BTreeMap;
+use std::fmt;
+use std::ops::RangeInclusive;
+use std::str::FromStr;
+
+use serde::{Deserialize, Serialize};
+
+use super::internal::WorkspaceState;
+use super::scroll::parse_range;
+
+/// A range of workspaces that's always shown, e.g. `1-5` or `DP-1:1-5` to
+/// only show them on one monitor.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "String", into = "String")]
+pub struct Persistent {
+    pub output: Option<String>,
+    pub range: RangeInclusive<i64>,
+}
+
+impl FromStr for Persistent {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let (output, range) = match s.rsplit_once(':') {
+            Some((output, range)) => (Some(output.to_string()), range),
+            None => (None, s),
+        };
+        Ok(Persistent {
+            output,
+            range: parse_range(range)?,
+        })
+    }
+}
+
+impl TryFrom<String> for Persistent {
+    type Error = String;
+
+    fn try_from(s: String) -> Result<Self, Self::Error> {
+        s.parse()
+    }
+}
+
+impl fmt::Display for Persistent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        if let Some(output) = &self.output {
+            write!(f, "{output}:")?;
+        }
+        write!(f, "{}-{}", self.range.start(), self.range.end())
+    }
+}
+
+impl From<Persistent> for String {
+    fn from(p: Persistent) -> String {
+        p.to_string()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    Focused,
+    Occupied,
+    Empty,
+}
+
+/// A workspace as shown on a bar.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct Entry {
+    pub id: i64,
+    pub status: Status,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub name: Option<String>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub output: Option<String>,
+}
+
+/// Entries by id and output, ranges on different outputs can share ids.
+type Entries = BTreeMap<(i64, Option<String>), Entry>;
+
+/// Every existing and persistent workspace, ordered by id and then output.
+///
+/// Workspaces count as occupied unless the WM reports them without windows.
+pub fn entries(state: &WorkspaceState, persistent: &[Persistent]) -> Vec<Entry> {
+    let mut entries = Entries::new();
+    for p in persistent {
+        for id in p.range.clone() {
+            let entry = Entry {
+                id,
+                status: Status::Empty,
+                name: None,
+                output: p.output.clone(),
+            };
+            entries.insert((id, p.output.clone()), entry);
+        }
+    }
+    for &id in state.workspaces() {
+        let info = state.info(id);
+        let output = info.and_then(|i| i.output.clone());
+        let status = match info.and_then(|i| i.windows) {
+            _ if id == state.active_workspace() => Status::Focused,
+            Some(0) => Status::Empty,
+            _ => Status::Occupied,
+        };
+        let key = placeholder(&entries, id, &output).unwrap_or((id, output));
+        let mut entry = entries.remove(&key).unwrap_or(Entry {
+            id,
+            status,
+            name: None,
+            output: None,
+        });
+        entry.status = status;
+        if let Some(info) = info {
+            entry.name = info.name.clone();
+            entry.output = info.output.clone().or(entry.output.take());
+        }
+        entries.insert((id, entry.output.clone()), entry);
+    }
+    entries.into_values().collect()
+}
+
+/// The persistent entry an existing workspace takes the place of, the one on
+/// its output or else the first one with its id.
+fn placeholder(
+    entries: &Entries,
+    id: i64,
+    output: &Option<String>,
+) -> Option<(i64, Option<String>)> {
+    let key = (id, output.clone());
+    if entries.contains_key(&key) {
+        return Some(key);
+    }
+    let (key, _) = entries.range((id, None)..).next()?;
+    (key.0 == id).then(|| key.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::internal::{WorkspaceChange, WorkspaceInfo};
+    use super::*;
+
+    #[test]
+    fn persistent_workspaces_are_parsed() {
+        assert_eq!(
+            "1-5".parse(),
+            Ok(Persistent {
+                output: None,
+                range: 1..=5
+            })
+        );
+        assert_eq!(
+            "DP-1:6-10".parse(),
+            Ok(Persistent {
+                output: Some("DP-1".into()),
+                range: 6..=10
+            })
+        );
+        assert!("DP-1".parse::<Persistent>().is_err());
+    }
+
+    #[test]
+    fn entries_cover_persistent_and_existing_workspaces() {
+        let mut state = WorkspaceState::new();
+        for (id, windows) in [(2, 3), (3, 0), (7, 1)] {
+            state.update(WorkspaceChange::Create(id));
+            state.update(WorkspaceChange::Describe(
+                id,
+                WorkspaceInfo {
+                    output: Some("eDP-1".into()),
+                    windows: Some(windows),
+                    ..Default::default()
+                },
+            ));
+        }
+        state.update(WorkspaceChange::Focus(3));
+        let persistent = ["DP-1:1-2".parse().unwrap(), "4-4".parse().unwrap()];
+        assert_eq!(
+            serde_json::to_string(&entries(&state, &persistent)).unwrap(),
+            r#"[{"id":1,"status":"empty","output":"DP-1"},
+                {"id":2,"status":"occupied","output":"eDP-1"},
+                {"id":3,"status":"focused","output":"eDP-1"},
+                {"id":4,"status":"empty"},
+                {"id":7,"status":"occupied","output":"eDP-1"}]"#
+                .replace(char::is_whitespace, "")
+        );
+    }
+
+    #[test]
+    fn outputs_can_share_persistent_ids() {
+        let mut state = WorkspaceState::new();
+        state.update(WorkspaceChange::Create(1));
+        state.update(WorkspaceChange::Describe(
+            1,
+            WorkspaceInfo {
+                output: Some("HDMI-A-1".into()),
+                ..Default::default()
+            },
+        ));
+        state.update(WorkspaceChange::Focus(1));
+        let persistent = ["DP-1:1-2".parse().unwrap(), "HDMI-A-1:1-2".parse().unwrap()];
+        assert_eq!(
+            serde_json::to_string(&entries(&state, &persistent)).unwrap(),
+            r#"[{"id":1,"status":"empty","output":"DP-1"},
+                {"id":1,"status":"focused","output":"HDMI-A-1"},
+                {"id":2,"status":"empty","output":"DP-1"},
+                {"id":2,"status":"empty","output":"HDMI-A-1"}]"#
+                .replace(char::is_whitespace, "")
+        );
+    }
+}
@@ -0,0 +1,185 @@
+//! Picks the next or previous workspace, e.g. for scrolling on a bar.
+//!
+//! Works on [`WorkspaceState`] only, so every backend scrolls the same way.
+
+use std::collections::BTreeSet;
+use std::ops::RangeInclusive;
+
+use super::internal::WorkspaceState;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Direction {
+    Next,
+    Prev,
+}
+
+/// How to pick the workspace to scroll to.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct Scroll {
+    /// Continue at the other end after the first or last workspace.
+    pub wrap: bool,
+    /// Only stop at workspaces that exist and have windows.
+    pub skip_empty: bool,
+    /// Only stop at workspaces on the active workspace's output.
+    pub per_monitor: bool,
+    /// Workspaces that always exist, nothing outside of it is visited.
+    pub range: Option<RangeInclusive<i64>>,
+}
+
+impl Scroll {
+    /// The workspaces that can be scrolled to, in order.
+    ///
+    /// Negative ids (e.g. Hyprland's special workspaces) are never visited.
+    fn candidates(&self, state: &WorkspaceState) -> BTreeSet<i64> {
+        let active = state.active_workspace();
+        let output = state.info(active).and_then(|info| info.output.as_ref());
+        let mut ids: BTreeSet<i64> = state.workspaces().iter().copied().collect();
+        if let Some(range) = &self.range {
+            ids.retain(|id| range.contains(id));
+            if !self.skip_empty {
+                ids.extend(range.clone());
+            }
+        }
+        ids.retain(|&id| {
+            let info = state.info(id);
+            // Workspaces that don't exist yet are created on the focused output.
+            let elsewhere = match (output, info.and_then(|i| i.output.as_ref())) {
+                (Some(active), Some(output)) => self.per_monitor && active != output,
+                _ => false,
+            };
+            let empty = id != active && info.and_then(|i| i.windows) == Some(0);
+            id >= 0 && !elsewhere && !(self.skip_empty && empty)
+        });
+        ids
+    }
+
+    /// The workspace to focus, `None` if there's nowhere to go.
+    pub fn target(&self, state: &WorkspaceState, direction: Direction) -> Option<i64> {
+        let active = state.active_workspace();
+        let ids = self.candidates(state);
+        let target = match direction {
+            Direction::Next => ids.range(active + 1..).next().or_else(|| match self.wrap {
+                true => ids.first(),
+                false => None,
+            }),
+            Direction::Prev => ids.range(..active).next_back().or_else(|| match self.wrap {
+                true => ids.last(),
+                false => None,
+            }),
+        };
+        target.copied().filter(|&id| id != active)
+    }
+}
+
+/// Parses a range of workspaces, e.g. `1-10`.
+pub fn parse_range(s: &str) -> Result<RangeInclusive<i64>, String> {
+    let (start, end) = s
+        .split_once('-')
+        .ok_or_else(|| format!("expected a range like 1-10, got {s:?}"))?;
+    let start: i64 = start
+        .trim()
+        .parse()
+        .map_err(|e| format!("{start:?}: {e}"))?;
+    let end: i64 = end.trim().parse().map_err(|e| format!("{end:?}: {e}"))?;
+    if start > end {
+        return Err(format!("{s:?} is empty"));
+    }
+    Ok(start..=end)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::super::internal::{WorkspaceChange, WorkspaceInfo};
+    use super::*;
+
+    /// Workspaces as `(id, output, windows)`, the first one is focused.
+    fn state(workspaces: &[(i64, &str, u32)]) -> WorkspaceState {
+        let mut state = WorkspaceState::new();
+        for (id, output, windows) in workspaces {
+            state.update(WorkspaceChange::Create(*id));
+            state.update(WorkspaceChange::Describe(
+                *id,
+                WorkspaceInfo {
+                    output: Some(output.to_string()),
+                    windows: Some(*windows),
+                    ..Default::default()
+                },
+            ));
+        }
+        state.update(WorkspaceChange::Focus(workspaces[0].0));
+        state
+    }
+
+    #[test]
+    fn existing_workspaces_are_visited_in_order() {
+        let state = state(&[
+            (3, "DP-1", 1),
+            (1, "DP-1", 1),
+            (-98, "DP-1", 1),
+            (7, "DP-1", 1),
+        ]);
+        let scroll = Scroll::default();
+        assert_eq!(scroll.target(&state, Direction::Next), Some(7));
+        assert_eq!(scroll.target(&state, Direction::Prev), Some(1));
+    }
+
+    #[test]
+    fn wrapping_continues_at_the_other_end() {
+        let state = state(&[(7, "DP-1", 1), (1, "DP-1", 1)]);
+        assert_eq!(Scroll::default().target(&state, Direction::Next), None);
+        let scroll = Scroll {
+            wrap: true,
+            ..Default::default()
+        };
+        assert_eq!(scroll.target(&state, Direction::Next), Some(1));
+        assert_eq!(scroll.target(&state, Direction::Prev), Some(1));
+    }
+
+    #[test]
+    fn ranges_add_missing_workspaces_and_bound_the_rest() {
+        let state = state(&[(5, "DP-1", 1), (12, "DP-1", 1)]);
+        let scroll = Scroll {
+            range: Some(1..=10),
+            ..Default::default()
+        };
+        assert_eq!(scroll.target(&state, Direction::Next), Some(6));
+        assert_eq!(scroll.target(&state, Direction::Prev), Some(4));
+        let state = self::state(&[(10, "DP-1", 1), (12, "DP-1", 1)]);
+        assert_eq!(scroll.target(&state, Direction::Next), None);
+        let scroll = Scroll {
+            wrap: true,
+            ..scroll
+        };
+        assert_eq!(scroll.target(&state, Direction::Next), Some(1));
+    }
+
+    #[test]
+    fn empty_workspaces_can_be_skipped() {
+        let state = state(&[(2, "DP-1", 0), (3, "DP-1", 0), (5, "DP-1", 2)]);
+        let scroll = Scroll {
+            skip_empty: true,
+            range: Some(1..=10),
+            ..Default::default()
+        };
+        assert_eq!(scroll.target(&state, Direction::Next), Some(5));
+        assert_eq!(scroll.target(&state, Direction::Prev), None);
+    }
+
+    #[test]
+    fn per_monitor_stays_on_the_active_output() {
+        let state = state(&[(2, "DP-1", 1), (3, "HDMI-A-1", 1), (4, "DP-1", 1)]);
+        let scroll = Scroll {
+            per_monitor: true,
+            ..Default::default()
+        };
+        assert_eq!(scroll.target(&state, Direction::Next), Some(4));
+        assert_eq!(Scroll::default().target(&state, Direction::Next), Some(3));
+    }
+
+    #[test]
+    fn ranges_are_parsed() {
+        assert_eq!(parse_range("1-10"), Ok(1..=10));
+        assert!(parse_range("10").is_err());
+        assert!(parse_range("10-1").is_err());
+    }
+}
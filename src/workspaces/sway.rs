@@ -1,44 +1,414 @@
-use swayipc::{Connection, EventType, Fallible};
+use swayipc::{Connection, EventStream, EventType, Fallible};
 
-use crate::workspaces::internal::{WorkspaceChange, WorkspaceState};
+use crate::workspaces::internal::{
+    Change, CompositorChange, WindowChange, WorkspaceChange, WorkspaceInfo,
+};
+use crate::workspaces::{Action, Subscriptions, Target, WindowManager};
 
-fn _listen_and_print() -> Fallible<()> {
-    let subs = [EventType::Workspace];
-    let mut state = WorkspaceState::new();
-    for event in Connection::new()?.subscribe(subs)? {
-        let swayipc::Event::Workspace(ws) = event? else {
-            continue;
-        };
-        let Some(node) = ws.current else {
-            eprintln!("Got a {:?} event with no workspace...?", ws.change);
-            continue;
+/// Sway's IPC, see `sway-ipc(7)`.
+pub struct Sway {
+    conn: Connection,
+    events: EventStream,
+    subs: Subscriptions,
+}
+
+impl Sway {
+    pub fn connect(subs: Subscriptions) -> anyhow::Result<Sway> {
+        Ok(Sway {
+            conn: Connection::new()?,
+            events: Connection::new()?.subscribe(event_types(subs, true))?,
+            subs,
+        })
+    }
+}
+
+/// The events `subs` needs, shared with i3, which has no input events.
+pub(super) fn event_types(subs: Subscriptions, inputs: bool) -> Vec<EventType> {
+    let mut types = vec![];
+    if subs.workspaces || subs.window {
+        types.push(EventType::Workspace);
+    }
+    if subs.window || subs.compositor {
+        types.push(EventType::Window);
+    }
+    if subs.compositor {
+        types.push(EventType::Mode);
+        if inputs {
+            types.push(EventType::Input);
+        }
+    }
+    types
+}
+
+/// Reads the next event from an i3/sway IPC event stream.
+///
+/// Returns `None` once the WM closes the socket.
+pub(super) fn next_ipc_event(events: &mut EventStream) -> anyhow::Result<Option<swayipc::Event>> {
+    match events.next() {
+        None => Ok(None),
+        Some(Ok(event)) => Ok(Some(event)),
+        Some(Err(swayipc::Error::Io(e))) if e.kind() == std::io::ErrorKind::UnexpectedEof => {
+            Ok(None)
+        }
+        Some(Err(e)) => Err(e.into()),
+    }
+}
+
+/// Numbered workspaces use their number, named ones fall back to the node id.
+fn workspace_id(name: Option<&str>, id: i64) -> i64 {
+    name.map_or(id, |v| v.parse::<i64>().unwrap_or(id))
+}
+
+/// Keeps the name when it isn't just the id, and the output.
+fn describe(id: i64, name: Option<String>, output: Option<String>) -> WorkspaceChange {
+    WorkspaceChange::Describe(
+        id,
+        WorkspaceInfo {
+            name: name.filter(|name| *name != id.to_string()),
+            output,
+            ..Default::default()
+        },
+    )
+}
+
+impl Sway {
+    fn workspace_snapshot(&mut self) -> anyhow::Result<Vec<WorkspaceChange>> {
+        let workspaces = self.conn.get_workspaces()?;
+        let mut changes = vec![];
+        let mut focused = None;
+        for ws in workspaces {
+            let id = workspace_id(Some(&ws.name), ws.id);
+            changes.push(WorkspaceChange::Create(id));
+            changes.push(describe(id, Some(ws.name), Some(ws.output)));
+            if ws.focused {
+                focused = Some(id);
+            }
+        }
+        changes.extend(focused.map(WorkspaceChange::Focus));
+        Ok(changes)
+    }
+}
+
+/// Translates an IPC event into changes to the workspaces.
+fn workspace_changes(event: swayipc::Event) -> Vec<WorkspaceChange> {
+    let swayipc::Event::Workspace(ws) = event else {
+        return vec![];
+    };
+    let Some(node) = ws.current else {
+        eprintln!("Got a {:?} event with no workspace...?", ws.change);
+        return vec![];
+    };
+    let id = workspace_id(node.name.as_deref(), node.id);
+    let change = match ws.change {
+        swayipc::WorkspaceChange::Init => WorkspaceChange::Create(id),
+        swayipc::WorkspaceChange::Empty => WorkspaceChange::Destroy(id),
+        swayipc::WorkspaceChange::Focus => WorkspaceChange::Focus(id),
+        // Moved to another output.
+        swayipc::WorkspaceChange::Move => return vec![describe(id, node.name, node.output)],
+        // swayipc::WorkspaceChange::Urgent => todo!(),
+        _ => return vec![],
+    };
+    if change == WorkspaceChange::Destroy(id) {
+        return vec![change];
+    }
+    vec![change, describe(id, node.name, node.output)]
+}
+
+impl WindowManager for Sway {
+    type Change = Change;
+
+    fn snapshot(&mut self) -> anyhow::Result<Vec<Change>> {
+        let mut changes = vec![];
+        if self.subs.workspaces {
+            changes.extend(self.workspace_snapshot()?.into_iter().map(Change::from));
+        }
+        changes.extend(window_snapshot(&mut self.conn, self.subs)?);
+        Ok(changes)
+    }
+
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<Change>>> {
+        let Some(event) = next_ipc_event(&mut self.events)? else {
+            return Ok(None);
         };
-        let id = node
-            .name
-            .map_or(node.id, |v| v.as_str().parse::<i64>().unwrap_or(node.id));
-        match ws.change {
-            swayipc::WorkspaceChange::Init => {
-                state.update(WorkspaceChange::Create(id));
+        let mut changes = window_changes(&mut self.conn, &event, self.subs)?;
+        if self.subs.workspaces {
+            changes.extend(workspace_changes(event).into_iter().map(Change::from));
+        }
+        Ok(Some(changes))
+    }
+}
+
+/// Quotes a workspace name for use in a command.
+fn quote(name: &str) -> String {
+    format!("\"{}\"", name.replace('\\', "\\\\").replace('"', "\\\""))
+}
+
+/// The workspace in command syntax, existing workspaces are referred to by
+/// their name since the listener's ids aren't always their number.
+fn workspace_arg(
+    target: &Target,
+    workspaces: &[swayipc::Workspace],
+    id_of: impl Fn(&swayipc::Workspace) -> i64,
+) -> String {
+    match target {
+        Target::Id(id) => match workspaces.iter().find(|ws| id_of(ws) == *id) {
+            Some(ws) => quote(&ws.name),
+            None => format!("number {id}"),
+        },
+        Target::Name(name) => quote(name),
+    }
+}
+
+/// The command performing `action` on the workspace `ws`.
+fn command(action: &Action, ws: &str) -> String {
+    match action {
+        // Workspaces are created by switching to them.
+        Action::Focus(_) | Action::Create(_) => format!("workspace {ws}"),
+        Action::MoveWindow(_) => format!("move container to workspace {ws}"),
+        Action::Rename(_, name) => format!("rename workspace {ws} to {}", quote(name)),
+    }
+}
+
+/// Performs `action` with a `RUN_COMMAND` message, shared with i3.
+pub(super) fn run_command(
+    conn: &mut Connection,
+    action: &Action,
+    id_of: impl Fn(&swayipc::Workspace) -> i64,
+) -> anyhow::Result<()> {
+    let workspaces = conn.get_workspaces()?;
+    let ws = workspace_arg(action.target(), &workspaces, id_of);
+    for outcome in conn.run_command(command(action, &ws))? {
+        outcome?;
+    }
+    Ok(())
+}
+
+pub fn run(action: &Action) -> anyhow::Result<()> {
+    run_command(&mut Connection::new()?, action, |ws| {
+        workspace_id(Some(&ws.name), ws.id)
+    })
+}
+
+/// Name of the currently focused workspace.
+fn focused_workspace(conn: &mut Connection) -> Fallible<Option<String>> {
+    Ok(conn
+        .get_workspaces()?
+        .into_iter()
+        .find(|ws| ws.focused)
+        .map(|ws| ws.name))
+}
+
+/// The window's Wayland app id, or X11 class when running under XWayland.
+fn window_class(node: &swayipc::Node) -> String {
+    node.app_id
+        .clone()
+        .or_else(|| {
+            node.window_properties
+                .as_ref()
+                .and_then(|p| p.class.clone())
+        })
+        .unwrap_or_default()
+}
+
+fn is_fullscreen(node: &swayipc::Node) -> bool {
+    node.fullscreen_mode.unwrap_or(0) > 0
+}
+
+/// The window's class, title and fullscreen state, all empty without a window.
+fn focus_changes(node: Option<&swayipc::Node>) -> [Change; 2] {
+    let focus = WindowChange::Focus {
+        class: node.map(window_class).unwrap_or_default(),
+        title: node.and_then(|n| n.name.clone()).unwrap_or_default(),
+    };
+    [
+        focus.into(),
+        WindowChange::Fullscreen(node.is_some_and(is_fullscreen)).into(),
+    ]
+}
+
+/// The focused window, and the binding mode, keyboard layout, fullscreen and
+/// floating state as they are right now, shared with i3.
+pub(super) fn window_snapshot(
+    conn: &mut Connection,
+    subs: Subscriptions,
+) -> anyhow::Result<Vec<Change>> {
+    let mut changes = vec![];
+    if !subs.window && !subs.compositor {
+        return Ok(changes);
+    }
+    let focused = conn.get_tree()?.find_focused(|n| n.focused);
+    if subs.window {
+        if let Some(name) = focused_workspace(conn)? {
+            changes.push(WindowChange::Workspace(name).into());
+        }
+        if let Some(node) = focused.as_ref().filter(|node| node.pid.is_some()) {
+            changes.extend(focus_changes(Some(node)));
+        }
+    }
+    if subs.compositor {
+        changes.push(CompositorChange::Submap(conn.get_binding_state()?).into());
+        if let Some(layout) = active_layout(conn) {
+            changes.push(CompositorChange::Layout(layout).into());
+        }
+        if let Some(node) = &focused {
+            changes.push(CompositorChange::Fullscreen(is_fullscreen(node)).into());
+            changes.push(
+                CompositorChange::Floating(node.node_type == swayipc::NodeType::FloatingCon).into(),
+            );
+        }
+    }
+    Ok(changes)
+}
+
+/// Translates an IPC event into changes to the focused window and the
+/// compositor state, shared with i3.
+pub(super) fn window_changes(
+    conn: &mut Connection,
+    event: &swayipc::Event,
+    subs: Subscriptions,
+) -> anyhow::Result<Vec<Change>> {
+    let mut changes = vec![];
+    if subs.compositor {
+        changes.extend(compositor_changes(event).into_iter().map(Change::from));
+    }
+    if !subs.window {
+        return Ok(changes);
+    }
+    match event {
+        // Closing or moving away the last window on a workspace doesn't focus
+        // anything else, so there's no focus event.
+        swayipc::Event::Window(win)
+            if matches!(
+                win.change,
+                swayipc::WindowChange::Close | swayipc::WindowChange::Move
+            ) =>
+        {
+            let focused = conn.get_tree()?.find_focused(|n| n.focused);
+            changes.extend(focus_changes(focused.filter(|n| n.pid.is_some()).as_ref()));
+            if let Some(name) = focused_workspace(conn)? {
+                changes.push(WindowChange::Workspace(name).into());
             }
-            swayipc::WorkspaceChange::Empty => {
-                state.update(WorkspaceChange::Destroy(id));
+        }
+        swayipc::Event::Window(win) if win.container.focused => {
+            let node = &win.container;
+            match win.change {
+                swayipc::WindowChange::Focus | swayipc::WindowChange::Title => {
+                    changes.extend(focus_changes(Some(node)));
+                }
+                swayipc::WindowChange::FullscreenMode => {
+                    changes.push(WindowChange::Fullscreen(is_fullscreen(node)).into());
+                }
+                _ => return Ok(changes),
             }
-            swayipc::WorkspaceChange::Focus => {
-                state.update(WorkspaceChange::Focus(id));
+            if let Some(name) = focused_workspace(conn)? {
+                changes.push(WindowChange::Workspace(name).into());
             }
-            // swayipc::WorkspaceChange::Urgent => todo!(),
-            _ => continue,
         }
-        if let Ok(data) = serde_json::to_string(&state) {
-            println!("{}", data);
+        swayipc::Event::Workspace(ws) if ws.change == swayipc::WorkspaceChange::Focus => {
+            if let Some(name) = ws.current.as_ref().and_then(|n| n.name.clone()) {
+                changes.push(WindowChange::Workspace(name).into());
+            }
         }
+        _ => (),
+    }
+    Ok(changes)
+}
+
+/// The first keyboard's active layout, `None` on i3 which has no inputs.
+fn active_layout(conn: &mut Connection) -> Option<String> {
+    conn.get_inputs()
+        .ok()?
+        .into_iter()
+        .find(|input| input.input_type == "keyboard")?
+        .xkb_active_layout_name
+}
+
+/// Translates an IPC event into changes to the compositor state.
+fn compositor_changes(event: &swayipc::Event) -> Vec<CompositorChange> {
+    match event {
+        swayipc::Event::Mode(mode) => vec![CompositorChange::Submap(mode.change.clone())],
+        swayipc::Event::Input(input) => match &input.input.xkb_active_layout_name {
+            Some(layout) if input.input.input_type == "keyboard" => {
+                vec![CompositorChange::Layout(layout.clone())]
+            }
+            _ => vec![],
+        },
+        swayipc::Event::Window(win) if win.container.focused => match win.change {
+            swayipc::WindowChange::FullscreenMode => vec![CompositorChange::Fullscreen(
+                win.container.fullscreen_mode.unwrap_or(0) > 0,
+            )],
+            swayipc::WindowChange::Floating => vec![CompositorChange::Floating(
+                win.container.node_type == swayipc::NodeType::FloatingCon,
+            )],
+            _ => vec![],
+        },
+        _ => vec![],
     }
-    Ok(())
 }
 
-pub fn listen_and_print() {
-    match _listen_and_print() {
-        Ok(_) => (),
-        Err(e) => panic!("{}", e),
+#[cfg(test)]
+mod tests {
+    use super::super::internal::CompositorState;
+    use super::*;
+
+    fn workspace(id: i64, num: i32, name: &str) -> swayipc::Workspace {
+        serde_json::from_str(&format!(
+            r#"{{"id":{id},"num":{num},"name":"{name}","visible":false,"focused":false,
+            "urgent":false,"rect":{{"x":0,"y":0,"width":1920,"height":1080}},"output":"eDP-1"}}"#
+        ))
+        .unwrap()
+    }
+
+    #[test]
+    fn existing_workspaces_are_referred_to_by_name() {
+        let workspaces = [workspace(4, 1, "1"), workspace(9, -1, "chat")];
+        let arg = |target| {
+            workspace_arg(&target, &workspaces, |ws| {
+                workspace_id(Some(&ws.name), ws.id)
+            })
+        };
+        assert_eq!(arg(Target::Id(1)), r#""1""#);
+        assert_eq!(arg(Target::Id(9)), r#""chat""#);
+        assert_eq!(arg(Target::Id(3)), "number 3");
+        assert_eq!(arg(Target::Name(r#"say "hi""#.into())), r#""say \"hi\"""#);
+    }
+
+    #[test]
+    fn actions_are_commands() {
+        let ws = r#""2: code""#;
+        assert_eq!(
+            command(&Action::Focus(Target::Id(2)), ws),
+            r#"workspace "2: code""#
+        );
+        assert_eq!(
+            command(&Action::MoveWindow(Target::Id(2)), ws),
+            r#"move container to workspace "2: code""#
+        );
+        assert_eq!(
+            command(&Action::Rename(Target::Id(2), "web".into()), ws),
+            r#"rename workspace "2: code" to "web""#
+        );
+    }
+
+    #[test]
+    fn mode_and_input_events_are_translated() {
+        let mode = r#"{"change":"resize","pango_markup":false}"#;
+        let input = r#"{"change":"xkb_layout","input":{"identifier":"1:1:AT_Translated_Set_2_keyboard",
+            "name":"AT Translated Set 2 keyboard","vendor":1,"product":1,"type":"keyboard",
+            "xkb_active_layout_name":"German","xkb_layout_names":["English (US)","German"],
+            "xkb_active_layout_index":1}}"#;
+        let mut state = CompositorState::new();
+        for event in [
+            swayipc::Event::Mode(serde_json::from_str(mode).unwrap()),
+            swayipc::Event::Input(Box::new(serde_json::from_str(input).unwrap())),
+        ] {
+            for change in compositor_changes(&event) {
+                state.update(change);
+            }
+        }
+        assert_eq!(
+            serde_json::to_string(&state).unwrap(),
+            r#"{"submap":"resize","layout":"German","special":"","fullscreen":false,"floating":false}"#
+        );
     }
 }
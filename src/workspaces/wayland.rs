@@ -0,0 +1,1057 @@
+use std::collections::{BTreeMap, VecDeque};
+use std::os::unix::net::UnixStream;
+
+use anyhow::bail;
+use wayland_client::backend::WaylandError;
+use wayland_client::protocol::{wl_output, wl_registry, wl_seat};
+use wayland_client::{
+    event_created_child, Connection, Dispatch, DispatchError, EventQueue, Proxy, QueueHandle, WEnum,
+};
+use wayland_protocols::ext::workspace::v1::client::{
+    ext_workspace_group_handle_v1::{self, ExtWorkspaceGroupHandleV1},
+    ext_workspace_handle_v1::{self, ExtWorkspaceHandleV1},
+    ext_workspace_manager_v1::{self, ExtWorkspaceManagerV1},
+};
+
+use super::internal::{WorkspaceChange, WorkspaceInfo};
+use super::{Action, Target, WindowManager};
+use river_status::{
+    zriver_output_status_v1::{self, ZriverOutputStatusV1},
+    zriver_seat_status_v1::{self, ZriverSeatStatusV1},
+    zriver_status_manager_v1::ZriverStatusManagerV1,
+};
+
+/// Client side bindings for river's `river-status-unstable-v1` protocol.
+pub mod river_status {
+    #![allow(non_upper_case_globals, non_camel_case_types, clippy::all)]
+    use wayland_client;
+    use wayland_client::protocol::*;
+
+    pub mod __interfaces {
+        use wayland_client::backend as wayland_backend;
+        use wayland_client::protocol::__interfaces::*;
+        wayland_scanner::generate_interfaces!(
+            "src/workspaces/protocols/river-status-unstable-v1.xml"
+        );
+    }
+    use self::__interfaces::*;
+
+    wayland_scanner::generate_client_code!("src/workspaces/protocols/river-status-unstable-v1.xml");
+}
+
+/// Compositors that expose workspaces through Wayland protocols instead of
+/// a private IPC socket.
+///
+/// `ext-workspace-v1` is used by e.g. labwc and wayfire, river reports its
+/// tags through `river-status-unstable-v1` instead. River tags are reported
+/// as workspaces `1..=32` of the focused output.
+pub struct Wayland {
+    queue: EventQueue<State>,
+    state: State,
+}
+
+#[derive(Default)]
+struct State {
+    /// Output names, keyed by the `wl_output`'s protocol id.
+    outputs: BTreeMap<u32, Option<String>>,
+    output_proxies: Vec<wl_output::WlOutput>,
+    seats: Vec<wl_seat::WlSeat>,
+    ext_manager: Option<ExtWorkspaceManagerV1>,
+    ext: ExtWorkspaces,
+    river_manager: Option<ZriverStatusManagerV1>,
+    river: RiverTags,
+    /// Set once the initial globals are known, outputs announced later are
+    /// watched as soon as they appear.
+    initialized: bool,
+    /// Complete workspace states waiting to be handed to the listener.
+    batches: VecDeque<Vec<WorkspaceChange>>,
+    finished: bool,
+}
+
+#[derive(Default)]
+struct ExtWorkspaces {
+    /// Outputs of each workspace group, keyed by the group's protocol id.
+    groups: BTreeMap<u32, Vec<u32>>,
+    group_handles: BTreeMap<u32, ExtWorkspaceGroupHandleV1>,
+    /// Keyed by the workspace handle's protocol id.
+    workspaces: BTreeMap<u32, ExtWorkspace>,
+    /// Ids handed out to workspaces without a numeric name.
+    last_named_id: i64,
+    /// Incremented every time a workspace becomes active.
+    activations: u64,
+}
+
+impl ExtWorkspaces {
+    fn next_named_id(&mut self) -> i64 {
+        self.last_named_id -= 1;
+        self.last_named_id
+    }
+
+    /// Gives numbered workspaces without an id their number, unless another
+    /// one has it already, e.g. on compositors with a "1" on every output.
+    fn number_workspaces(&mut self) {
+        let unnumbered: Vec<_> = self
+            .workspaces
+            .iter()
+            .filter(|(_, ws)| ws.id.is_none())
+            .filter_map(|(&key, ws)| Some((key, ws.name.as_ref()?.parse::<i64>().ok()?)))
+            .collect();
+        for (key, num) in unnumbered {
+            let taken = self.workspaces.values().any(|ws| ws.id == Some(num));
+            let id = match taken {
+                true => self.next_named_id(),
+                false => num,
+            };
+            if let Some(ws) = self.workspaces.get_mut(&key) {
+                ws.id = Some(id);
+            }
+        }
+    }
+}
+
+#[derive(Default)]
+struct ExtWorkspace {
+    handle: Option<ExtWorkspaceHandleV1>,
+    id: Option<i64>,
+    name: Option<String>,
+    coordinates: Vec<u32>,
+    group: Option<u32>,
+    active: bool,
+    activated_at: u64,
+}
+
+#[derive(Default)]
+struct RiverTags {
+    /// Focused and occupied tags, keyed by the `wl_output`'s protocol id.
+    focused: BTreeMap<u32, u32>,
+    occupied: BTreeMap<u32, u32>,
+    focused_output: Option<u32>,
+    dirty: bool,
+}
+
+/// Decodes a wayland `array` argument of native endian `u32`s.
+fn u32_array(bytes: &[u8]) -> Vec<u32> {
+    bytes
+        .chunks_exact(4)
+        .map(|c| u32::from_ne_bytes([c[0], c[1], c[2], c[3]]))
+        .collect()
+}
+
+/// Workspace ids (1-based) of the bits set in a river tag mask.
+fn tags(mask: u32) -> impl Iterator<Item = i64> {
+    (0..32)
+        .filter(move |bit| mask & (1 << bit) != 0)
+        .map(|bit| bit + 1)
+}
+
+impl State {
+    fn output_name(&self, output: Option<u32>) -> Option<String> {
+        self.outputs.get(&output?).cloned().flatten()
+    }
+
+    /// The full workspace state according to `ext-workspace-v1`.
+    fn ext_changes(&self) -> Vec<WorkspaceChange> {
+        let mut changes = vec![WorkspaceChange::Reset];
+        let mut focused: Option<(u64, i64)> = None;
+        for ws in self.ext.workspaces.values() {
+            let Some(id) = ws.id else {
+                continue;
+            };
+            let output = ws
+                .group
+                .and_then(|g| self.ext.groups.get(&g))
+                .and_then(|outputs| outputs.first().copied());
+            changes.push(WorkspaceChange::Create(id));
+            changes.push(WorkspaceChange::Describe(
+                id,
+                WorkspaceInfo {
+                    name: ws.name.clone(),
+                    output: self.output_name(output),
+                    index: ws.coordinates.first().map(|c| c + 1),
+                    windows: None,
+                },
+            ));
+            // The protocol has no notion of focus, the most recently
+            // activated workspace is the best guess.
+            if ws.active && focused.is_none_or(|(at, _)| ws.activated_at > at) {
+                focused = Some((ws.activated_at, id));
+            }
+        }
+        changes.extend(focused.map(|(_, id)| WorkspaceChange::Focus(id)));
+        changes
+    }
+
+    /// The full workspace state according to river's tags.
+    fn river_changes(&self) -> Vec<WorkspaceChange> {
+        let mut changes = vec![WorkspaceChange::Reset];
+        let output = self
+            .river
+            .focused_output
+            .or_else(|| self.river.focused.keys().next().copied());
+        let Some(output) = output else {
+            return changes;
+        };
+        let focused = self.river.focused.get(&output).copied().unwrap_or(0);
+        let occupied = self.river.occupied.get(&output).copied().unwrap_or(0);
+        for id in tags(focused | occupied) {
+            changes.push(WorkspaceChange::Create(id));
+            changes.push(WorkspaceChange::Describe(
+                id,
+                WorkspaceInfo {
+                    output: self.output_name(Some(output)),
+                    ..Default::default()
+                },
+            ));
+        }
+        changes.extend(tags(focused).next().map(WorkspaceChange::Focus));
+        changes
+    }
+
+    fn watch_river_output(&self, output: &wl_output::WlOutput, qh: &QueueHandle<State>) {
+        if let Some(manager) = &self.river_manager {
+            manager.get_river_output_status(output, qh, output.id().protocol_id());
+        }
+    }
+}
+
+impl Wayland {
+    /// Connects to the compositor in `WAYLAND_DISPLAY`.
+    pub fn connect() -> anyhow::Result<Wayland> {
+        Wayland::from_connection(Connection::connect_to_env()?)
+    }
+
+    pub fn from_socket(stream: UnixStream) -> anyhow::Result<Wayland> {
+        Wayland::from_connection(Connection::from_socket(stream)?)
+    }
+
+    fn from_connection(conn: Connection) -> anyhow::Result<Wayland> {
+        let mut queue = conn.new_event_queue();
+        let qh = queue.handle();
+        conn.display().get_registry(&qh, ());
+        let mut state = State::default();
+        queue.roundtrip(&mut state)?;
+        if state.ext_manager.is_none() && state.river_manager.is_none() {
+            bail!("The compositor supports neither ext-workspace-v1 nor river-status-unstable-v1.");
+        }
+        if let Some(manager) = &state.river_manager {
+            for output in &state.output_proxies {
+                state.watch_river_output(output, &qh);
+            }
+            for seat in &state.seats {
+                manager.get_river_seat_status(seat, &qh, ());
+            }
+        }
+        state.initialized = true;
+        // Receive the initial state.
+        queue.roundtrip(&mut state)?;
+        Ok(Wayland { queue, state })
+    }
+
+    /// Returns the next complete state, `None` once the compositor is gone.
+    fn next_batch(&mut self) -> anyhow::Result<Option<Vec<WorkspaceChange>>> {
+        loop {
+            if let Some(batch) = self.state.batches.pop_front() {
+                return Ok(Some(batch));
+            }
+            if self.state.finished {
+                return Ok(None);
+            }
+            match self.queue.blocking_dispatch(&mut self.state) {
+                Ok(_) => (),
+                Err(DispatchError::Backend(WaylandError::Io(_))) => return Ok(None),
+                Err(e) => return Err(e.into()),
+            }
+            // river-status has no `done` event, every dispatch is a batch.
+            if self.state.river.dirty {
+                self.state.river.dirty = false;
+                let batch = self.state.river_changes();
+                self.state.batches.push_back(batch);
+            }
+        }
+    }
+}
+
+impl WindowManager for Wayland {
+    type Change = WorkspaceChange;
+
+    fn snapshot(&mut self) -> anyhow::Result<Vec<WorkspaceChange>> {
+        if self.state.river_manager.is_some() {
+            self.state.river.dirty = false;
+            self.state.batches.clear();
+            return Ok(self.state.river_changes());
+        }
+        // Only the latest state matters, every batch is complete.
+        let latest = self.state.batches.drain(..).next_back();
+        match latest {
+            Some(batch) => Ok(batch),
+            None => Ok(self.next_batch()?.unwrap_or_default()),
+        }
+    }
+
+    fn next_event(&mut self) -> anyhow::Result<Option<Vec<WorkspaceChange>>> {
+        self.next_batch()
+    }
+}
+
+impl ExtWorkspaces {
+    fn find(&self, target: &Target) -> Option<&ExtWorkspace> {
+        self.workspaces.values().find(|ws| match target {
+            Target::Id(id) => ws.id == Some(*id),
+            Target::Name(name) => ws.name.as_ref() == Some(name),
+        })
+    }
+}
+
+impl Wayland {
+    /// Performs `action` through `ext-workspace-v1`.
+    ///
+    /// The protocol can't move windows or rename workspaces, and river's
+    /// tags are read only.
+    pub fn run(&mut self, action: &Action) -> anyhow::Result<()> {
+        let Some(manager) = &self.state.ext_manager else {
+            bail!("river-status is read only, use `riverctl set-focused-tags` instead.");
+        };
+        let ext = &self.state.ext;
+        match action {
+            Action::Focus(target) => match ext.find(target).and_then(|ws| ws.handle.as_ref()) {
+                Some(handle) => handle.activate(),
+                None => bail!("No such workspace: {target:?}"),
+            },
+            Action::Create(target) => {
+                // Create the workspace next to the active one.
+                let group = ext
+                    .workspaces
+                    .values()
+                    .filter(|ws| ws.active)
+                    .max_by_key(|ws| ws.activated_at)
+                    .and_then(|ws| ws.group)
+                    .and_then(|g| ext.group_handles.get(&g))
+                    .or_else(|| ext.group_handles.values().next());
+                let Some(group) = group else {
+                    bail!("The compositor has no workspace groups.");
+                };
+                let name = match target {
+                    Target::Id(id) => id.to_string(),
+                    Target::Name(name) => name.clone(),
+                };
+                group.create_workspace(name);
+            }
+            Action::MoveWindow(_) | Action::Rename(..) => {
+                bail!("ext-workspace-v1 can't move windows or rename workspaces.")
+            }
+        }
+        manager.commit();
+        self.queue.roundtrip(&mut self.state)?;
+        Ok(())
+    }
+}
+
+pub fn run(action: &Action) -> anyhow::Result<()> {
+    Wayland::connect()?.run(action)
+}
+
+impl Dispatch<wl_registry::WlRegistry, ()> for State {
+    fn event(
+        state: &mut Self,
+        registry: &wl_registry::WlRegistry,
+        event: wl_registry::Event,
+        _: &(),
+        _: &Connection,
+        qh: &QueueHandle<Self>,
+    ) {
+        let wl_registry::Event::Global {
+            name,
+            interface,
+            version,
+        } = event
+        else {
+            return;
+        };
+        match interface.as_str() {
+            "wl_output" => {
+                // `name` events were added in version 4.
+                let output: wl_output::WlOutput = registry.bind(name, version.min(4), qh, ());
+                state.outputs.insert(output.id().protocol_id(), None);
+                if state.initialized {
+                    state.watch_river_output(&output, qh);
+                }
+                state.output_proxies.push(output);
+            }
+            "wl_seat" => state.seats.push(registry.bind(name, 1, qh, ())),
+            "ext_workspace_manager_v1" => {
+                state.ext_manager = Some(registry.bind(name, 1, qh, ()));
+            }
+            "zriver_status_manager_v1" => {
+                state.river_manager = Some(registry.bind(name, 1, qh, ()));
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<wl_output::WlOutput, ()> for State {
+    fn event(
+        state: &mut Self,
+        output: &wl_output::WlOutput,
+        event: wl_output::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        if let wl_output::Event::Name { name } = event {
+            state.outputs.insert(output.id().protocol_id(), Some(name));
+            state.river.dirty = true;
+        }
+    }
+}
+
+impl Dispatch<wl_seat::WlSeat, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &wl_seat::WlSeat,
+        _: wl_seat::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+impl Dispatch<ExtWorkspaceManagerV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ExtWorkspaceManagerV1,
+        event: ext_workspace_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            ext_workspace_manager_v1::Event::WorkspaceGroup { workspace_group } => {
+                let id = workspace_group.id().protocol_id();
+                state.ext.groups.insert(id, vec![]);
+                state.ext.group_handles.insert(id, workspace_group);
+            }
+            ext_workspace_manager_v1::Event::Workspace { workspace } => {
+                let id = workspace.id().protocol_id();
+                let ws = ExtWorkspace {
+                    handle: Some(workspace),
+                    ..Default::default()
+                };
+                state.ext.workspaces.insert(id, ws);
+            }
+            ext_workspace_manager_v1::Event::Done => {
+                state.ext.number_workspaces();
+                let batch = state.ext_changes();
+                state.batches.push_back(batch);
+            }
+            ext_workspace_manager_v1::Event::Finished => state.finished = true,
+            _ => (),
+        }
+    }
+
+    event_created_child!(State, ExtWorkspaceManagerV1, [
+        ext_workspace_manager_v1::EVT_WORKSPACE_GROUP_OPCODE => (ExtWorkspaceGroupHandleV1, ()),
+        ext_workspace_manager_v1::EVT_WORKSPACE_OPCODE => (ExtWorkspaceHandleV1, ()),
+    ]);
+}
+
+impl Dispatch<ExtWorkspaceGroupHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        group: &ExtWorkspaceGroupHandleV1,
+        event: ext_workspace_group_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let id = group.id().protocol_id();
+        match event {
+            ext_workspace_group_handle_v1::Event::OutputEnter { output } => {
+                let outputs = state.ext.groups.entry(id).or_default();
+                outputs.push(output.id().protocol_id());
+            }
+            ext_workspace_group_handle_v1::Event::OutputLeave { output } => {
+                let outputs = state.ext.groups.entry(id).or_default();
+                outputs.retain(|&o| o != output.id().protocol_id());
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceEnter { workspace } => {
+                let ws = workspace.id().protocol_id();
+                if let Some(ws) = state.ext.workspaces.get_mut(&ws) {
+                    ws.group = Some(id);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::WorkspaceLeave { workspace } => {
+                let ws = workspace.id().protocol_id();
+                if let Some(ws) = state.ext.workspaces.get_mut(&ws) {
+                    ws.group = ws.group.filter(|&g| g != id);
+                }
+            }
+            ext_workspace_group_handle_v1::Event::Removed => {
+                state.ext.groups.remove(&id);
+                state.ext.group_handles.remove(&id);
+                group.destroy();
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<ExtWorkspaceHandleV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        handle: &ExtWorkspaceHandleV1,
+        event: ext_workspace_handle_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        let key = handle.id().protocol_id();
+        if let ext_workspace_handle_v1::Event::Removed = event {
+            state.ext.workspaces.remove(&key);
+            handle.destroy();
+            return;
+        }
+        let ext = &mut state.ext;
+        let Some(ws) = ext.workspaces.get_mut(&key) else {
+            return;
+        };
+        match event {
+            ext_workspace_handle_v1::Event::Name { name } => {
+                if ws.name.as_ref() == Some(&name) {
+                    return;
+                }
+                // Numbered workspaces use their number, once the batch is
+                // done, named ones get a negative id, like Hyprland's special
+                // workspaces.
+                ws.id = match (name.parse::<i64>(), ws.id) {
+                    (Ok(_), _) => None,
+                    (Err(_), Some(id)) if id < 0 => Some(id),
+                    (Err(_), _) => {
+                        ext.last_named_id -= 1;
+                        Some(ext.last_named_id)
+                    }
+                };
+                ws.name = Some(name);
+            }
+            ext_workspace_handle_v1::Event::Coordinates { coordinates } => {
+                ws.coordinates = u32_array(&coordinates);
+            }
+            ext_workspace_handle_v1::Event::State { state } => {
+                let active = match state {
+                    WEnum::Value(s) => s.contains(ext_workspace_handle_v1::State::Active),
+                    WEnum::Unknown(bits) => bits & 1 != 0,
+                };
+                if active && !ws.active {
+                    ext.activations += 1;
+                    ws.activated_at = ext.activations;
+                }
+                ws.active = active;
+            }
+            _ => (),
+        }
+    }
+}
+
+impl Dispatch<ZriverStatusManagerV1, ()> for State {
+    fn event(
+        _: &mut Self,
+        _: &ZriverStatusManagerV1,
+        _: river_status::zriver_status_manager_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+    }
+}
+
+/// User data is the protocol id of the `wl_output` the status is for.
+impl Dispatch<ZriverOutputStatusV1, u32> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZriverOutputStatusV1,
+        event: zriver_output_status_v1::Event,
+        output: &u32,
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zriver_output_status_v1::Event::FocusedTags { tags } => {
+                state.river.focused.insert(*output, tags);
+            }
+            zriver_output_status_v1::Event::ViewTags { tags } => {
+                let occupied = u32_array(&tags).into_iter().fold(0, |acc, t| acc | t);
+                state.river.occupied.insert(*output, occupied);
+            }
+            _ => return,
+        }
+        state.river.dirty = true;
+    }
+}
+
+impl Dispatch<ZriverSeatStatusV1, ()> for State {
+    fn event(
+        state: &mut Self,
+        _: &ZriverSeatStatusV1,
+        event: zriver_seat_status_v1::Event,
+        _: &(),
+        _: &Connection,
+        _: &QueueHandle<Self>,
+    ) {
+        match event {
+            zriver_seat_status_v1::Event::FocusedOutput { output } => {
+                state.river.focused_output = Some(output.id().protocol_id());
+            }
+            zriver_seat_status_v1::Event::UnfocusedOutput { output } => {
+                let id = output.id().protocol_id();
+                state.river.focused_output = state.river.focused_output.filter(|&o| o != id);
+            }
+            _ => return,
+        }
+        state.river.dirty = true;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::atomic::{AtomicBool, Ordering};
+    use std::sync::Arc;
+    use std::thread::{self, JoinHandle};
+    use std::time::Duration;
+
+    use wayland_protocols::ext::workspace::v1::server::{
+        ext_workspace_group_handle_v1::ExtWorkspaceGroupHandleV1 as Group,
+        ext_workspace_handle_v1::{self as handle, ExtWorkspaceHandleV1 as Handle},
+        ext_workspace_manager_v1::{self as manager, ExtWorkspaceManagerV1 as Manager},
+    };
+    use wayland_server::backend::{ClientData, ClientId, DisconnectReason};
+    use wayland_server::protocol::{wl_output::WlOutput, wl_seat::WlSeat};
+    use wayland_server::{
+        Client, DataInit, Dispatch, Display, DisplayHandle, GlobalDispatch, New, Resource,
+    };
+
+    use super::super::internal::WorkspaceState;
+    use super::*;
+    use river::{
+        zriver_output_status_v1::ZriverOutputStatusV1 as OutputStatus,
+        zriver_seat_status_v1::ZriverSeatStatusV1 as SeatStatus,
+        zriver_status_manager_v1::{self as river_manager, ZriverStatusManagerV1 as RiverManager},
+    };
+
+    /// Server side bindings for `river-status-unstable-v1`.
+    mod river {
+        #![allow(non_upper_case_globals, non_camel_case_types, clippy::all)]
+        use wayland_server;
+        use wayland_server::protocol::*;
+
+        use super::super::river_status::__interfaces::*;
+
+        wayland_scanner::generate_server_code!(
+            "src/workspaces/protocols/river-status-unstable-v1.xml"
+        );
+    }
+
+    /// A minimal compositor, the workspaces are announced once the client
+    /// binds the workspace manager.
+    #[derive(Default)]
+    struct Compositor {
+        /// Every output has its own group of workspaces, numbered from 1.
+        per_output: bool,
+        outputs: Vec<WlOutput>,
+        workspaces: Vec<Handle>,
+        /// The workspace activated since the last commit.
+        pending: Option<Handle>,
+    }
+
+    struct TestClient;
+
+    impl ClientData for TestClient {
+        fn initialized(&self, _: ClientId) {}
+        fn disconnected(&self, _: ClientId, _: DisconnectReason) {}
+    }
+
+    /// Runs the compositor on its own thread until dropped.
+    struct Server {
+        stop: Arc<AtomicBool>,
+        thread: Option<JoinHandle<()>>,
+    }
+
+    impl Drop for Server {
+        fn drop(&mut self) {
+            self.stop.store(true, Ordering::Relaxed);
+            if let Some(thread) = self.thread.take() {
+                thread.join().unwrap();
+            }
+        }
+    }
+
+    /// What the compositor advertises.
+    #[derive(Clone, Copy, PartialEq)]
+    enum Protocols {
+        Ext,
+        /// `ext-workspace-v1` with a second output, and a group per output.
+        ExtPerOutput,
+        River,
+    }
+
+    /// Starts a compositor advertising an output and either the
+    /// `ext-workspace-v1` or the `river-status` globals.
+    fn serve(protocols: Protocols) -> (Server, UnixStream) {
+        let (client, server) = UnixStream::pair().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut display = Display::<Compositor>::new().unwrap();
+            let dh = display.handle();
+            dh.create_global::<Compositor, WlOutput, ()>(4, ());
+            if protocols == Protocols::ExtPerOutput {
+                dh.create_global::<Compositor, WlOutput, ()>(4, ());
+            }
+            if protocols == Protocols::River {
+                dh.create_global::<Compositor, WlSeat, ()>(1, ());
+                dh.create_global::<Compositor, RiverManager, ()>(1, ());
+            } else {
+                dh.create_global::<Compositor, Manager, ()>(1, ());
+            }
+            display
+                .handle()
+                .insert_client(server, Arc::new(TestClient))
+                .unwrap();
+            let mut state = Compositor {
+                per_output: protocols == Protocols::ExtPerOutput,
+                ..Default::default()
+            };
+            while !stopped.load(Ordering::Relaxed) {
+                display.dispatch_clients(&mut state).unwrap();
+                display.flush_clients().unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let server = Server {
+            stop,
+            thread: Some(thread),
+        };
+        (server, client)
+    }
+
+    impl GlobalDispatch<WlOutput, ()> for Compositor {
+        fn bind(
+            state: &mut Self,
+            _: &DisplayHandle,
+            _: &Client,
+            resource: New<WlOutput>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let output = data_init.init(resource, ());
+            let name = ["DP-1", "HDMI-A-1"][state.outputs.len()];
+            output.name(name.to_string());
+            output.done();
+            state.outputs.push(output);
+        }
+    }
+
+    impl GlobalDispatch<Manager, ()> for Compositor {
+        fn bind(
+            state: &mut Self,
+            dh: &DisplayHandle,
+            client: &Client,
+            resource: New<Manager>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            let manager = data_init.init(resource, ());
+            if state.per_output {
+                for (i, output) in state.outputs.iter().enumerate() {
+                    let group = client
+                        .create_resource::<Group, (), Self>(dh, 1, ())
+                        .unwrap();
+                    manager.workspace_group(&group);
+                    group.output_enter(output);
+                    for name in ["1", "2"] {
+                        let ws = client
+                            .create_resource::<Handle, (), Self>(dh, 1, ())
+                            .unwrap();
+                        manager.workspace(&ws);
+                        ws.name(name.to_string());
+                        ws.state(if i == 0 && name == "1" {
+                            handle::State::Active
+                        } else {
+                            handle::State::empty()
+                        });
+                        group.workspace_enter(&ws);
+                    }
+                }
+                manager.done();
+                return;
+            }
+            let group = client
+                .create_resource::<Group, (), Self>(dh, 1, ())
+                .unwrap();
+            manager.workspace_group(&group);
+            group.output_enter(&state.outputs[0]);
+            let mut workspaces = vec![];
+            for (i, name) in ["1", "2", "chat"].into_iter().enumerate() {
+                let ws = client
+                    .create_resource::<Handle, (), Self>(dh, 1, ())
+                    .unwrap();
+                manager.workspace(&ws);
+                ws.name(name.to_string());
+                ws.coordinates((i as u32).to_ne_bytes().to_vec());
+                ws.state(if i == 0 {
+                    handle::State::Active
+                } else {
+                    handle::State::empty()
+                });
+                group.workspace_enter(&ws);
+                workspaces.push(ws);
+            }
+            manager.done();
+            // Switch to the second workspace.
+            workspaces[0].state(handle::State::empty());
+            workspaces[1].state(handle::State::Active);
+            manager.done();
+            // Remove the named workspace.
+            group.workspace_leave(&workspaces[2]);
+            workspaces[2].removed();
+            manager.done();
+            workspaces.pop();
+            state.workspaces = workspaces;
+        }
+    }
+
+    impl GlobalDispatch<WlSeat, ()> for Compositor {
+        fn bind(
+            _: &mut Self,
+            _: &DisplayHandle,
+            _: &Client,
+            resource: New<WlSeat>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl GlobalDispatch<RiverManager, ()> for Compositor {
+        fn bind(
+            _: &mut Self,
+            _: &DisplayHandle,
+            _: &Client,
+            resource: New<RiverManager>,
+            _: &(),
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            data_init.init(resource, ());
+        }
+    }
+
+    impl Dispatch<RiverManager, ()> for Compositor {
+        fn request(
+            state: &mut Self,
+            _: &Client,
+            _: &RiverManager,
+            request: river_manager::Request,
+            _: &(),
+            _: &DisplayHandle,
+            data_init: &mut DataInit<'_, Self>,
+        ) {
+            match request {
+                river_manager::Request::GetRiverOutputStatus { id, .. } => {
+                    let status = data_init.init(id, ());
+                    // Tags 1 and 3 have views, tag 2 is focused.
+                    status.focused_tags(0b010);
+                    let views: Vec<u8> = [0b001_u32, 0b100, 0b001]
+                        .iter()
+                        .flat_map(|t| t.to_ne_bytes())
+                        .collect();
+                    status.view_tags(views);
+                }
+                river_manager::Request::GetRiverSeatStatus { id, .. } => {
+                    let status = data_init.init(id, ());
+                    status.focused_output(&state.outputs[0]);
+                }
+                _ => (),
+            }
+        }
+    }
+
+    /// Nothing to do for requests the tests don't make.
+    macro_rules! ignore_requests {
+        ($($iface:ty),*) => {$(
+            impl Dispatch<$iface, ()> for Compositor {
+                fn request(
+                    _: &mut Self,
+                    _: &Client,
+                    _: &$iface,
+                    _: <$iface as Resource>::Request,
+                    _: &(),
+                    _: &DisplayHandle,
+                    _: &mut DataInit<'_, Self>,
+                ) {
+                }
+            }
+        )*};
+    }
+
+    ignore_requests!(WlOutput, WlSeat, Group, OutputStatus, SeatStatus);
+
+    impl Dispatch<Handle, ()> for Compositor {
+        fn request(
+            state: &mut Self,
+            _: &Client,
+            resource: &Handle,
+            request: handle::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+            if let handle::Request::Activate = request {
+                state.pending = Some(resource.clone());
+            }
+        }
+    }
+
+    impl Dispatch<Manager, ()> for Compositor {
+        fn request(
+            state: &mut Self,
+            _: &Client,
+            resource: &Manager,
+            request: manager::Request,
+            _: &(),
+            _: &DisplayHandle,
+            _: &mut DataInit<'_, Self>,
+        ) {
+            let manager::Request::Commit = request else {
+                return;
+            };
+            let Some(active) = state.pending.take() else {
+                return;
+            };
+            for ws in &state.workspaces {
+                ws.state(if *ws == active {
+                    handle::State::Active
+                } else {
+                    handle::State::empty()
+                });
+            }
+            resource.done();
+        }
+    }
+
+    fn apply(state: &mut WorkspaceState, changes: Vec<WorkspaceChange>) -> String {
+        for change in changes {
+            state.update(change);
+        }
+        serde_json::to_string(state).unwrap()
+    }
+
+    #[test]
+    fn snapshot_is_the_latest_batch() {
+        let (_server, socket) = serve(Protocols::Ext);
+        let mut wm = Wayland::from_socket(socket).unwrap();
+        let mut state = WorkspaceState::new();
+        assert_eq!(
+            apply(&mut state, wm.snapshot().unwrap()),
+            r#"{"active_workspace":2,"workspaces":[1,2],"info":{
+                "1":{"name":"1","output":"DP-1","index":1},
+                "2":{"name":"2","output":"DP-1","index":2}}}"#
+                .replace(|c: char| c.is_whitespace(), "")
+        );
+    }
+
+    #[test]
+    fn ext_workspace_batches_are_applied_in_order() {
+        let (_server, socket) = serve(Protocols::Ext);
+        let mut wm = Wayland::from_socket(socket).unwrap();
+        let batches: Vec<_> = wm.state.batches.drain(..).collect();
+        let mut state = WorkspaceState::new();
+        let states: Vec<_> = batches.into_iter().map(|b| apply(&mut state, b)).collect();
+        let expected = [
+            r#"{"active_workspace":1,"workspaces":[-1,1,2],"info":{
+                "-1":{"name":"chat","output":"DP-1","index":3},
+                "1":{"name":"1","output":"DP-1","index":1},
+                "2":{"name":"2","output":"DP-1","index":2}}}"#,
+            r#"{"active_workspace":2,"workspaces":[-1,1,2],"info":{
+                "-1":{"name":"chat","output":"DP-1","index":3},
+                "1":{"name":"1","output":"DP-1","index":1},
+                "2":{"name":"2","output":"DP-1","index":2}}}"#,
+            r#"{"active_workspace":2,"workspaces":[1,2],"info":{
+                "1":{"name":"1","output":"DP-1","index":1},
+                "2":{"name":"2","output":"DP-1","index":2}}}"#,
+        ]
+        .map(|s| s.replace(|c: char| c.is_whitespace(), ""));
+        assert_eq!(states, expected);
+    }
+
+    #[test]
+    fn outputs_can_share_workspace_numbers() {
+        let (_server, socket) = serve(Protocols::ExtPerOutput);
+        let mut wm = Wayland::from_socket(socket).unwrap();
+        let mut state = WorkspaceState::new();
+        assert_eq!(
+            apply(&mut state, wm.snapshot().unwrap()),
+            r#"{"active_workspace":1,"workspaces":[-2,-1,1,2],"info":{
+                "-2":{"name":"2","output":"HDMI-A-1"},
+                "-1":{"name":"1","output":"HDMI-A-1"},
+                "1":{"name":"1","output":"DP-1"},
+                "2":{"name":"2","output":"DP-1"}}}"#
+                .replace(|c: char| c.is_whitespace(), "")
+        );
+    }
+
+    #[test]
+    fn workspaces_are_activated() {
+        let (_server, socket) = serve(Protocols::Ext);
+        let mut wm = Wayland::from_socket(socket).unwrap();
+        let mut state = WorkspaceState::new();
+        apply(&mut state, wm.snapshot().unwrap());
+        wm.run(&Action::Focus(Target::Name("1".into()))).unwrap();
+        let changes = wm.next_event().unwrap().unwrap();
+        assert!(apply(&mut state, changes).starts_with(r#"{"active_workspace":1,"#));
+        let err = wm.run(&Action::Focus(Target::Id(7))).unwrap_err();
+        assert_eq!(err.to_string(), "No such workspace: Id(7)");
+        assert!(wm
+            .run(&Action::Rename(Target::Id(1), "web".into()))
+            .is_err());
+    }
+
+    #[test]
+    fn river_tags_are_workspaces() {
+        let (_server, socket) = serve(Protocols::River);
+        let mut wm = Wayland::from_socket(socket).unwrap();
+        let mut state = WorkspaceState::new();
+        assert_eq!(
+            apply(&mut state, wm.snapshot().unwrap()),
+            r#"{"active_workspace":2,"workspaces":[1,2,3],"info":{
+                "1":{"output":"DP-1"},"2":{"output":"DP-1"},"3":{"output":"DP-1"}}}"#
+                .replace(|c: char| c.is_whitespace(), "")
+        );
+    }
+
+    #[test]
+    fn missing_protocols_are_an_error() {
+        let (client, server) = UnixStream::pair().unwrap();
+        let stop = Arc::new(AtomicBool::new(false));
+        let stopped = stop.clone();
+        let thread = thread::spawn(move || {
+            let mut display = Display::<Compositor>::new().unwrap();
+            display
+                .handle()
+                .insert_client(server, Arc::new(TestClient))
+                .unwrap();
+            while !stopped.load(Ordering::Relaxed) {
+                display
+                    .dispatch_clients(&mut Compositor::default())
+                    .unwrap();
+                display.flush_clients().unwrap();
+                thread::sleep(Duration::from_millis(1));
+            }
+        });
+        let _server = Server {
+            stop,
+            thread: Some(thread),
+        };
+        assert!(Wayland::from_socket(client).is_err());
+    }
+}